@@ -12,6 +12,8 @@ use crate::DecodeFailed;
 
 pub struct Bitstream<'a> {
     buffer: &'a [u8],
+    // Length of the buffer the bitstream was created with.
+    len: usize,
     // Next number in the bitstream.
     n: u16,
     // How many bits left in the current `n`.
@@ -22,11 +24,17 @@ impl<'a> Bitstream<'a> {
     pub fn new(buffer: &'a [u8]) -> Self {
         Self {
             buffer,
+            len: buffer.len(),
             n: 0,
             remaining: 0,
         }
     }
 
+    /// The position of the next bit to be read, in bits since the start of the buffer.
+    pub fn bit_position(&self) -> usize {
+        (self.len - self.buffer.len()) * 8 - self.remaining as usize
+    }
+
     // Advance the buffer to the next 16-bit integer.
     fn advance_buffer(&mut self) -> Result<(), DecodeFailed> {
         if self.buffer.is_empty() {
@@ -130,6 +138,7 @@ impl<'a> Bitstream<'a> {
             // Read the two words.
             let mut advanced_stream = Self {
                 buffer: self.buffer,
+                len: self.len,
                 n: self.n,
                 remaining: self.remaining,
             };
@@ -181,6 +190,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::unusual_byte_groupings)]
     fn read_sequential() {
         // 0..=10 and padding using the least amount of bits possible, read LTR
         let ns = [0b0_1_10_11_100_101_110_1u16, 0b11_1000_1001_1010_00u16];
@@ -200,6 +210,19 @@ mod tests {
             });
     }
 
+    #[test]
+    fn bit_position() {
+        let bytes = [0x56, 0x78, 0x12, 0x34];
+        let mut bitstream = Bitstream::new(&bytes);
+        assert_eq!(bitstream.bit_position(), 0);
+        bitstream.read_bits(3).unwrap();
+        assert_eq!(bitstream.bit_position(), 3);
+        bitstream.read_bits(16).unwrap();
+        assert_eq!(bitstream.bit_position(), 19);
+        bitstream.align().unwrap();
+        assert_eq!(bitstream.bit_position(), 32);
+    }
+
     #[test]
     fn read_32le() {
         let bytes = [0x56, 0x78, 0x12, 0x34];
@@ -317,6 +340,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::unusual_byte_groupings)]
     fn read_equals_peek() {
         for index in 0..20 {
             let n =
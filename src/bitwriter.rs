@@ -0,0 +1,248 @@
+use alloc::vec::Vec;
+
+/// Produces the bitstream read by the decoder.
+///
+/// > An LZXD bitstream is encoded as a sequence of aligned 16-bit integers stored in the
+/// > least-significant- byte to most-significant-byte order, also known as byte-swapped,
+/// > or little-endian, words.
+///
+/// Bits are packed into each of those integers starting with the most-significant bit, so
+/// values written with more bits than are left in the current integer continue in the next
+/// one. This is the layout of every chunk of compressed data, and it's what the encoder uses
+/// to write them, but it can also be used to craft compressed data by hand.
+///
+/// ```
+/// use ::lzxd::BitWriter;
+///
+/// let mut writer = BitWriter::new();
+/// writer.write_bits(0b101, 3);
+/// writer.write_bits(0x1234, 16);
+/// writer.align();
+/// writer.write_raw(b"abc");
+/// writer.write_byte(0);
+/// assert_eq!(writer.finish(), [0x46, 0xA2, 0x00, 0x80, b'a', b'b', b'c', 0]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    // Bits written so far into the next number, from the most-significant end.
+    n: u16,
+    // How many bits of `n` have been written.
+    used: u8,
+}
+
+impl BitWriter {
+    /// Creates a new writer with no bits written.
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            n: 0,
+            used: 0,
+        }
+    }
+
+    /// The position of the next bit to be written, in bits since the start of the output.
+    pub fn bit_position(&self) -> usize {
+        self.bytes.len() * 8 + self.used as usize
+    }
+
+    /// Write no more than 16 bits (one word).
+    fn write_bits_oneword(&mut self, value: u16, bits: u8) {
+        debug_assert!(bits <= 16);
+        if bits == 0 {
+            return;
+        }
+        // Higher bits would spill into those written before.
+        let value = ((value as u32) & ((1 << bits) - 1)) as u16;
+
+        let free = 16 - self.used;
+        if bits < free {
+            self.n |= value << (free - bits);
+            self.used += bits;
+        } else {
+            // Fill the current number with the high bits, and carry the rest into the next one.
+            let rest = bits - free;
+            self.n |= ((value as u32) >> rest) as u16;
+            self.bytes.extend(self.n.to_le_bytes());
+            self.n = ((value as u32) << (16 - rest)) as u16;
+            self.used = rest;
+        }
+    }
+
+    /// Writes the lowest `bits` bits of the `value`, from the most-significant one, ignoring
+    /// the rest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bits` is over 32, as no more than 32 bits can be written at once.
+    pub fn write_bits(&mut self, value: u32, bits: u8) {
+        if bits <= 16 {
+            self.write_bits_oneword(value as u16, bits)
+        } else {
+            assert!(bits <= 32);
+
+            // Write the two words.
+            self.write_bits_oneword((value >> 16) as u16, bits - 16);
+            self.write_bits_oneword(value as u16, 16);
+        }
+    }
+
+    /// Writes a single bit.
+    pub fn write_bit(&mut self, value: bool) {
+        self.write_bits_oneword(value as u16, 1)
+    }
+
+    /// Writes a 24-bit value as 16 bits followed by 8 bits, like the size of a block.
+    pub fn write_u24_be(&mut self, value: u32) {
+        self.write_bits(value >> 8, 16);
+        self.write_bits(value & 0xff, 8);
+    }
+
+    /// Writes a 32-bit value with its low 16 bits first, like the repeated offsets of an
+    /// uncompressed block.
+    pub fn write_u32_le(&mut self, value: u32) {
+        self.write_bits(value & 0xffff, 16);
+        self.write_bits(value >> 16, 16);
+    }
+
+    /// Pads the current number with zeros, or writes a whole number of zeros if there's no
+    /// current number, just as the `Bitstream` skips them when aligning.
+    pub fn align(&mut self) {
+        self.write_bits(0, 16 - self.used);
+    }
+
+    /// Copies the bytes to the output ignoring the representation, like the data of an
+    /// uncompressed block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the output is not aligned, which [`Self::align`] takes care of.
+    pub fn write_raw(&mut self, bytes: &[u8]) {
+        assert_eq!(self.used, 0);
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Copies a single byte to the output ignoring the representation, like the byte of
+    /// padding after an uncompressed block of an odd size, which the decoder skips before
+    /// reading the next block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the output is not aligned, which [`Self::align`] takes care of.
+    pub fn write_byte(&mut self, value: u8) {
+        self.write_raw(&[value]);
+    }
+
+    /// Pads the last number with zeros if needed and returns the bytes written.
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.used != 0 {
+            self.bytes.extend(self.n.to_le_bytes());
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bitstream;
+
+    #[test]
+    fn write_sequential() {
+        let mut writer = BitWriter::new();
+        let bit_lengths = [1u8, 1, 2, 2, 3, 3, 3, 3, 4, 4, 4];
+        bit_lengths
+            .iter()
+            .enumerate()
+            .for_each(|(value, &bits)| writer.write_bits(value as u32, bits));
+
+        #[allow(clippy::unusual_byte_groupings)]
+        let ns = [0b0_1_10_11_100_101_110_1u16, 0b11_1000_1001_1010_00u16];
+        let mut bytes = Vec::new();
+        ns.iter().for_each(|n| bytes.extend(n.to_le_bytes()));
+        assert_eq!(writer.finish(), bytes);
+    }
+
+    #[test]
+    fn write_ignores_high_bits() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0xFFFF_FFF5, 4);
+        writer.write_bits(0xFFF1_2345, 20);
+        writer.write_bits(0x8000_0001, 8);
+        assert_eq!(writer.finish(), [0x23, 0x51, 0x01, 0x45]);
+    }
+
+    #[test]
+    fn write_read_back() {
+        let values = [
+            (1, 1),
+            (0x1234, 13),
+            (0xABCDE, 20),
+            (7, 3),
+            (0xDEAD_BEEF, 32),
+        ];
+        let mut writer = BitWriter::new();
+        values
+            .iter()
+            .for_each(|&(value, bits)| writer.write_bits(value, bits));
+        writer.write_u24_be(0x123456);
+        let bytes = writer.finish();
+
+        let mut bitstream = Bitstream::new(&bytes);
+        values.iter().for_each(|&(value, bits)| {
+            assert_eq!(bitstream.read_bits(bits), Ok(value));
+        });
+        assert_eq!(bitstream.read_u24_be(), Ok(0x123456));
+        assert_eq!(bitstream.remaining_bytes(), 0);
+    }
+
+    #[test]
+    fn write_odd_raw_read_back() {
+        let mut writer = BitWriter::new();
+        writer.align();
+        writer.write_raw(b"abc");
+        writer.write_byte(0);
+        writer.write_bits(0b11, 2);
+        let bytes = writer.finish();
+        assert_eq!(bytes.len(), 8);
+
+        let mut bitstream = Bitstream::new(&bytes);
+        bitstream.align().unwrap();
+        let mut raw = [0; 3];
+        bitstream.read_raw(&mut raw).unwrap();
+        assert_eq!(&raw, b"abc");
+        assert_eq!(bitstream.read_byte(), Some(0));
+        assert_eq!(bitstream.read_bits(2), Ok(0b11));
+        assert_eq!(bitstream.remaining_bytes(), 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn write_raw_unaligned() {
+        let mut writer = BitWriter::new();
+        writer.write_bit(true);
+        writer.write_raw(b"abc");
+    }
+
+    #[test]
+    fn write_aligned_read_back() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.align();
+        assert_eq!(writer.bit_position(), 16);
+        writer.align();
+        assert_eq!(writer.bit_position(), 32);
+        writer.write_u32_le(0x1234_5678);
+        writer.write_raw(b"abc");
+        let bytes = writer.finish();
+
+        let mut bitstream = Bitstream::new(&bytes);
+        assert_eq!(bitstream.read_bits(3), Ok(0b101));
+        bitstream.align().unwrap();
+        bitstream.align().unwrap();
+        assert_eq!(bitstream.read_u32_le(), Ok(0x1234_5678));
+        let mut raw = [0; 3];
+        bitstream.read_raw(&mut raw).unwrap();
+        assert_eq!(&raw, b"abc");
+    }
+}
@@ -1,4 +1,9 @@
-use crate::{Bitstream, DecodeFailed, DecoderState, Tree};
+use alloc::vec::Vec;
+
+use crate::{
+    Bitstream, BlockInfo, BlockTrees, BlockType, DecodeFailed, DecoderState, Tree, Variant,
+    WindowSize, WIM_DEFAULT_BLOCK_SIZE,
+};
 
 // if position_slot < 4 {
 //     0
@@ -7,7 +12,7 @@ use crate::{Bitstream, DecodeFailed, DecoderState, Tree};
 // } else {
 //     (position_slot - 2) / 2
 // }
-const FOOTER_BITS: [u8; 289] = [
+pub(crate) const FOOTER_BITS: [u8; 289] = [
     0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
     13, 14, 14, 15, 15, 16, 16, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17,
     17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17, 17,
@@ -27,7 +32,7 @@ const FOOTER_BITS: [u8; 289] = [
 // } else {
 //     BASE_POSITION[position_slot - 1] + (1 << FOOTER_BITS[position_slot - 1])
 // }
-const BASE_POSITION: [u32; 290] = [
+pub(crate) const BASE_POSITION: [u32; 290] = [
     0, 1, 2, 3, 4, 6, 8, 12, 16, 24, 32, 48, 64, 96, 128, 192, 256, 384, 512, 768, 1024, 1536,
     2048, 3072, 4096, 6144, 8192, 12288, 16384, 24576, 32768, 49152, 65536, 98304, 131072, 196608,
     262144, 393216, 524288, 655360, 786432, 917504, 1048576, 1179648, 1310720, 1441792, 1572864,
@@ -64,16 +69,22 @@ struct DecodeInfo<'a> {
     aligned_offset_tree: Option<&'a Tree>,
     main_tree: &'a Tree,
     length_tree: Option<&'a Tree>,
+    extra_length: bool,
 }
 
 #[derive(Debug)]
 pub enum Decoded {
     Single(u8),
-    Match { offset: usize, length: usize },
+    Match {
+        offset: usize,
+        length: usize,
+        /// The slot of the repeated offset used by the match, if any.
+        repeated_slot: Option<u8>,
+    },
     Read(usize),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Kind {
     Verbatim {
         main_tree: Tree,
@@ -91,6 +102,7 @@ pub enum Kind {
 
 /// Note that this is not the block header, but the head of the block's body, which includes
 /// everything except the tail of the block data (either uncompressed data or token sequence).
+#[derive(Clone)]
 pub struct Block {
     /// Only 24 bits may be used.
     pub remaining: u32,
@@ -138,6 +150,7 @@ fn decode_element(
         aligned_offset_tree,
         main_tree,
         length_tree,
+        extra_length,
     }: DecodeInfo,
 ) -> Result<Decoded, DecodeFailed> {
     // Decoding Matches and Literals (Aligned and Verbatim Blocks)
@@ -215,28 +228,27 @@ fn decode_element(
         // > (or match length, as specified in section 2.6) value is 257, and an
         // > encoded Extra Length field follows the other match encoding components,
         // > as specified in section 2.6.7, in the bitstream.
-
-        // TODO for some reason, if we do this, parsing .xnb files with window size
-        //      64KB, it breaks and stops decompressing correctly, but no idea why.
-        /*
-        let match_length = if match_length == 257 {
+        //
+        // This is only part of LZX DELTA. Classic LZX (as used by XNB and CAB files) caps the
+        // match length at 257 and never has this field, which is why it's behind a variant.
+        let match_length = if extra_length && match_length == 257 {
             // Decode the extra length.
-            let extra_len = if bitstream.read_bit() != 0 {
-                if bitstream.read_bit() != 0 {
-                    if bitstream.read_bit() != 0 {
+            let extra_len = if bitstream.read_bit()? != 0 {
+                if bitstream.read_bit()? != 0 {
+                    if bitstream.read_bit()? != 0 {
                         // > Prefix 0b111; Number of bits to decode 15;
-                        bitstream.read_bits(15)
+                        bitstream.read_bits(15)?
                     } else {
                         // > Prefix 0b110; Number of bits to decode 12;
-                        bitstream.read_bits(12) + 1024 + 256
+                        bitstream.read_bits(12)? + 1024 + 256
                     }
                 } else {
                     // > Prefix 0b10; Number of bits to decode 10;
-                    bitstream.read_bits(10) + 256
+                    bitstream.read_bits(10)? + 256
                 }
             } else {
                 // > Prefix 0b0; Number of bits to decode 8;
-                bitstream.read_bits(8)
+                bitstream.read_bits(8)?
             };
 
             // Get the match length (if match length >= 257).
@@ -244,14 +256,14 @@ fn decode_element(
             // > Base value to add to decoded value 257 + …
             257 + extra_len
         } else {
-            match_length as u16
+            match_length as u32
         };
-        */
 
         // Get match length and offset. Perform copy and paste work.
         Decoded::Match {
             offset: match_offset as usize,
             length: match_length as usize,
+            repeated_slot: (position_slot < 3).then_some(position_slot as u8),
         }
     })
 }
@@ -265,7 +277,11 @@ impl Block {
         // > Of the eight possible values, only three are valid values for the Block Type
         // > field.
         let kind = bitstream.read_bits(3)? as u8;
-        let size = bitstream.read_u24_be()?;
+        let size = match state.variant {
+            Variant::Wim if bitstream.read_bit()? != 0 => WIM_DEFAULT_BLOCK_SIZE,
+            Variant::Wim if state.window_size == WindowSize::KB32 => bitstream.read_bits(16)?,
+            _ => bitstream.read_u24_be()?,
+        };
         if size == 0 {
             return Err(DecodeFailed::InvalidBlockSize(size));
         }
@@ -322,11 +338,62 @@ impl Block {
         })
     }
 
+    /// Public information about this block.
+    pub(crate) fn info(&self) -> BlockInfo<'_> {
+        let (block_type, trees, repeated_offsets) = match &self.kind {
+            Kind::Verbatim {
+                main_tree,
+                length_tree,
+            } => (
+                BlockType::Verbatim,
+                BlockTrees {
+                    aligned_offset: None,
+                    main: Some(main_tree.path_lengths()),
+                    length: length_tree.as_ref().map(Tree::path_lengths),
+                },
+                None,
+            ),
+            Kind::AlignedOffset {
+                aligned_offset_tree,
+                main_tree,
+                length_tree,
+            } => (
+                BlockType::AlignedOffset,
+                BlockTrees {
+                    aligned_offset: Some(aligned_offset_tree.path_lengths()),
+                    main: Some(main_tree.path_lengths()),
+                    length: length_tree.as_ref().map(Tree::path_lengths),
+                },
+                None,
+            ),
+            Kind::Uncompressed { r } => (
+                BlockType::Uncompressed,
+                BlockTrees {
+                    aligned_offset: None,
+                    main: None,
+                    length: None,
+                },
+                Some(*r),
+            ),
+        };
+
+        BlockInfo {
+            block_type,
+            size: self.size,
+            remaining: self.remaining,
+            trees,
+            repeated_offsets,
+        }
+    }
+
     pub(crate) fn decode_element(
         &self,
         bitstream: &mut Bitstream,
         r: &mut [u32; 3],
+        variant: Variant,
     ) -> Result<Decoded, DecodeFailed> {
+        let extra_length = variant == Variant::LzxDelta;
+
         match &self.kind {
             Kind::Verbatim {
                 main_tree,
@@ -338,6 +405,7 @@ impl Block {
                     aligned_offset_tree: None,
                     main_tree,
                     length_tree: length_tree.as_ref(),
+                    extra_length,
                 },
             ),
             Kind::AlignedOffset {
@@ -351,6 +419,7 @@ impl Block {
                     aligned_offset_tree: Some(aligned_offset_tree),
                     main_tree,
                     length_tree: length_tree.as_ref(),
+                    extra_length,
                 },
             ),
             Kind::Uncompressed { r: new_r } => {
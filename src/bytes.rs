@@ -0,0 +1,90 @@
+//! Little-endian reading of the structures found in the containers of LZX compressed data.
+
+/// Reads little-endian values from a byte slice, failing with the given error when it's too
+/// short.
+#[derive(Clone)]
+pub(crate) struct ByteReader<'a, E> {
+    data: &'a [u8],
+    pos: usize,
+    eof: E,
+}
+
+impl<'a, E: Copy> ByteReader<'a, E> {
+    pub fn new(data: &'a [u8], eof: E) -> Self {
+        Self { data, pos: 0, eof }
+    }
+
+    /// Moves to the given offset from the start of the data, which may be past the end.
+    pub fn seek(&mut self, pos: usize) {
+        self.pos = pos;
+    }
+
+    /// Whether there's nothing left to read.
+    pub fn is_at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn read(&mut self, len: usize) -> Result<&'a [u8], E> {
+        let bytes = self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or(self.eof)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], E> {
+        Ok(self.read(N)?.try_into().unwrap())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, E> {
+        Ok(self.read(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, E> {
+        self.read_array().map(u16::from_le_bytes)
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, E> {
+        self.read_array().map(u32::from_le_bytes)
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, E> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    /// Reads a string terminated by a null byte, returning it without the terminator.
+    pub fn read_cstr(&mut self) -> Result<&'a [u8], E> {
+        let rest = self.data.get(self.pos..).ok_or(self.eof)?;
+        let len = rest.iter().position(|&b| b == 0).ok_or(self.eof)?;
+        self.pos += len + 1;
+        Ok(&rest[..len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_values() {
+        let data = [
+            1, 0x34, 0x12, 0x78, 0x56, 0x34, 0x12, 8, 7, 6, 5, 4, 3, 2, 1, b'a', b'b', 0, 9,
+        ];
+        let mut reader = ByteReader::new(&data[..], ());
+        assert_eq!(reader.read_u8(), Ok(1));
+        assert_eq!(reader.read_u16(), Ok(0x1234));
+        assert_eq!(reader.read_u32(), Ok(0x1234_5678));
+        assert_eq!(reader.read_u64(), Ok(0x0102_0304_0506_0708));
+        assert_eq!(reader.read_cstr(), Ok(&b"ab"[..]));
+        assert_eq!(reader.read_u16(), Err(()));
+        assert!(!reader.is_at_end());
+        assert_eq!(reader.read_u8(), Ok(9));
+        assert!(reader.is_at_end());
+        assert_eq!(reader.read_cstr(), Err(()));
+
+        reader.seek(usize::MAX);
+        assert_eq!(reader.read(1), Err(()));
+    }
+}
@@ -0,0 +1,589 @@
+//! Extraction of files from Microsoft Cabinet (CAB) files.
+//!
+//! A cabinet stores files one after the other in folders, each of which is compressed as a
+//! whole and split into data blocks of up to [`MAX_CHUNK_SIZE`] bytes once decompressed. When
+//! a folder uses LZX compression, every data block holds exactly one chunk of the LZXD data
+//! stream of the folder, so the blocks are fed to a single [`Lzxd`] decoder in order.
+//!
+//! Only files stored entirely in one cabinet can be extracted, and only from folders that
+//! are uncompressed or use LZX compression. The checksums of the data blocks are not verified.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use ::lzxd::cab::Cabinet;
+//!
+//! let data = std::fs::read("data.cab")?;
+//! let cabinet = Cabinet::new(&data)?;
+//! for file in cabinet.files() {
+//!     println!("{} ({} bytes)", String::from_utf8_lossy(file.name()), file.size());
+//! }
+//! let readme = cabinet.extract("README.TXT")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`MAX_CHUNK_SIZE`]: ../constant.MAX_CHUNK_SIZE.html
+//! [`Lzxd`]: ../struct.Lzxd.html
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::bytes::ByteReader;
+use crate::{DecompressError, Lzxd, WindowSize};
+
+/// Magic bytes at the start of every cabinet.
+const SIGNATURE: &[u8; 4] = b"MSCF";
+
+/// The cabinet is continued from a previous one.
+const FLAG_PREV_CABINET: u16 = 0x0001;
+
+/// The cabinet continues in a next one.
+const FLAG_NEXT_CABINET: u16 = 0x0002;
+
+/// The cabinet has reserved space in its header, folders and data blocks.
+const FLAG_RESERVE_PRESENT: u16 = 0x0004;
+
+/// The lowest folder index used for files that span several cabinets.
+const FOLDER_CONTINUED: u16 = 0xFFFD;
+
+/// The error type used when a cabinet cannot be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CabError {
+    /// The data does not start with the signature of a cabinet.
+    InvalidSignature,
+
+    /// The data ended before the cabinet was fully read.
+    UnexpectedEof,
+
+    /// The cabinet contains a value that doesn't make sense, such as a file outside of its
+    /// folder.
+    InvalidData,
+
+    /// The cabinet has no file with the requested name.
+    FileNotFound,
+
+    /// The file or its folder is partially stored in another cabinet.
+    SpansCabinets,
+
+    /// The folder uses a compression method that is not supported.
+    UnsupportedCompression(Compression),
+
+    /// The LZX compressed data of a folder could not be decompressed.
+    Decompress(DecompressError),
+}
+
+impl fmt::Display for CabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use CabError::*;
+
+        match self {
+            InvalidSignature => write!(f, "data is not a cabinet"),
+            UnexpectedEof => write!(f, "reached end of data without fully reading the cabinet"),
+            InvalidData => write!(f, "cabinet contains invalid data"),
+            FileNotFound => write!(f, "file not found in the cabinet"),
+            SpansCabinets => write!(f, "data is partially stored in another cabinet"),
+            UnsupportedCompression(compression) => {
+                write!(f, "{:?} compression is not supported", compression)
+            }
+            Decompress(e) => write!(f, "failed to decompress folder: {}", e),
+        }
+    }
+}
+
+impl core::error::Error for CabError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Decompress(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecompressError> for CabError {
+    fn from(e: DecompressError) -> Self {
+        Self::Decompress(e)
+    }
+}
+
+/// The compression method of a folder, from its `typeCompress` field.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Compression {
+    /// The data is stored as-is.
+    None,
+
+    /// The data is compressed with MSZIP, which is not supported.
+    MsZip,
+
+    /// The data is compressed with Quantum, which is not supported.
+    Quantum,
+
+    /// The data is compressed with LZX, using the window size in the high byte of the field.
+    Lzx(WindowSize),
+
+    /// The field has a value not known to be valid.
+    Unknown(u16),
+}
+
+impl Compression {
+    fn from_type(type_compress: u16) -> Self {
+        match type_compress & 0x000F {
+            0 => Self::None,
+            1 => Self::MsZip,
+            2 => Self::Quantum,
+            3 => match (type_compress >> 8) & 0x1F {
+                bits @ 15..=21 => Self::Lzx(WindowSize::from_value(1 << bits).unwrap()),
+                _ => Self::Unknown(type_compress),
+            },
+            _ => Self::Unknown(type_compress),
+        }
+    }
+}
+
+/// A folder of the cabinet, holding the compressed data of some of its files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Folder {
+    data_offset: u32,
+    data_blocks: u16,
+    compression: Compression,
+}
+
+impl Folder {
+    /// The compression method of the data.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// How many data blocks make up the folder.
+    pub fn data_blocks(&self) -> u16 {
+        self.data_blocks
+    }
+}
+
+/// A file stored in the cabinet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CabFile<'a> {
+    name: &'a [u8],
+    size: u32,
+    offset: u32,
+    folder: u16,
+    date: u16,
+    time: u16,
+    attributes: u16,
+}
+
+impl<'a> CabFile<'a> {
+    /// The name of the file, which may include a path with backslashes as separators.
+    ///
+    /// It's encoded as UTF-8 if the `_A_NAME_IS_UTF` attribute (0x80) is set, or in some
+    /// unspecified code page otherwise.
+    pub fn name(&self) -> &'a [u8] {
+        self.name
+    }
+
+    /// The size of the file once extracted.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The index of the folder holding the file. Files that span several cabinets use the
+    /// special values 0xFFFD to 0xFFFF instead.
+    pub fn folder(&self) -> u16 {
+        self.folder
+    }
+
+    /// The offset of the file in the uncompressed data of its folder.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// The date of the file, in MS-DOS format.
+    pub fn date(&self) -> u16 {
+        self.date
+    }
+
+    /// The time of the file, in MS-DOS format.
+    pub fn time(&self) -> u16 {
+        self.time
+    }
+
+    /// The attributes of the file, such as read-only (0x01) or hidden (0x02).
+    pub fn attributes(&self) -> u16 {
+        self.attributes
+    }
+}
+
+/// A cabinet file, read from a buffer holding all of its data.
+#[derive(Debug, Clone)]
+pub struct Cabinet<'a> {
+    data: &'a [u8],
+    folders: Vec<Folder>,
+    files: Vec<CabFile<'a>>,
+    /// The size of the reserved area of every data block.
+    data_reserve: usize,
+}
+
+impl<'a> Cabinet<'a> {
+    /// Reads the header of the cabinet, along with its folders and files.
+    pub fn new(data: &'a [u8]) -> Result<Self, CabError> {
+        let mut reader = ByteReader::new(data, CabError::UnexpectedEof);
+        if reader.read(4)? != SIGNATURE {
+            return Err(CabError::InvalidSignature);
+        }
+
+        reader.read_u32()?; // reserved1
+        reader.read_u32()?; // cbCabinet
+        reader.read_u32()?; // reserved2
+        let files_offset = reader.read_u32()?;
+        reader.read_u32()?; // reserved3
+        reader.read_u16()?; // versionMinor, versionMajor
+        let folder_count = reader.read_u16()?;
+        let file_count = reader.read_u16()?;
+        let flags = reader.read_u16()?;
+        reader.read_u16()?; // setID
+        reader.read_u16()?; // iCabinet
+
+        let (mut folder_reserve, mut data_reserve) = (0, 0);
+        if flags & FLAG_RESERVE_PRESENT != 0 {
+            let header_reserve = reader.read_u16()?;
+            folder_reserve = reader.read_u8()? as usize;
+            data_reserve = reader.read_u8()? as usize;
+            reader.read(header_reserve as usize)?;
+        }
+        if flags & FLAG_PREV_CABINET != 0 {
+            reader.read_cstr()?; // szCabinetPrev
+            reader.read_cstr()?; // szDiskPrev
+        }
+        if flags & FLAG_NEXT_CABINET != 0 {
+            reader.read_cstr()?; // szCabinetNext
+            reader.read_cstr()?; // szDiskNext
+        }
+
+        let folders = (0..folder_count)
+            .map(|_| {
+                let folder = Folder {
+                    data_offset: reader.read_u32()?,
+                    data_blocks: reader.read_u16()?,
+                    compression: Compression::from_type(reader.read_u16()?),
+                };
+                reader.read(folder_reserve)?;
+                Ok(folder)
+            })
+            .collect::<Result<Vec<_>, CabError>>()?;
+
+        reader.seek(files_offset as usize);
+        let files = (0..file_count)
+            .map(|_| {
+                Ok(CabFile {
+                    size: reader.read_u32()?,
+                    offset: reader.read_u32()?,
+                    folder: reader.read_u16()?,
+                    date: reader.read_u16()?,
+                    time: reader.read_u16()?,
+                    attributes: reader.read_u16()?,
+                    name: reader.read_cstr()?,
+                })
+            })
+            .collect::<Result<Vec<_>, CabError>>()?;
+
+        Ok(Self {
+            data,
+            folders,
+            files,
+            data_reserve,
+        })
+    }
+
+    /// The folders of the cabinet.
+    pub fn folders(&self) -> &[Folder] {
+        &self.folders
+    }
+
+    /// The files of the cabinet, in the order they're stored.
+    pub fn files(&self) -> &[CabFile<'a>] {
+        &self.files
+    }
+
+    /// Finds the file with the given name, ignoring the case of ASCII letters as Windows does.
+    pub fn find(&self, name: &str) -> Option<&CabFile<'a>> {
+        self.files
+            .iter()
+            .find(|file| file.name.eq_ignore_ascii_case(name.as_bytes()))
+    }
+
+    /// Decompresses the folder with the given index, stopping once at least `len` bytes have
+    /// been decompressed.
+    fn decompress_folder_until(&self, index: usize, len: usize) -> Result<Vec<u8>, CabError> {
+        let folder = self.folders.get(index).ok_or(CabError::InvalidData)?;
+        let mut lzxd = match folder.compression {
+            Compression::None => None,
+            Compression::Lzx(window_size) => Some(Lzxd::new(window_size)),
+            compression => return Err(CabError::UnsupportedCompression(compression)),
+        };
+
+        let mut output = Vec::new();
+        let mut reader = ByteReader::new(self.data, CabError::UnexpectedEof);
+        reader.seek(folder.data_offset as usize);
+        for _ in 0..folder.data_blocks {
+            if output.len() >= len {
+                break;
+            }
+
+            reader.read_u32()?; // csum
+            let compressed_len = reader.read_u16()?;
+            let uncompressed_len = reader.read_u16()?;
+            reader.read(self.data_reserve)?;
+            let block = reader.read(compressed_len as usize)?;
+
+            // The rest of the block is in the next cabinet, which says how long it is.
+            if uncompressed_len == 0 {
+                return Err(CabError::SpansCabinets);
+            }
+
+            match lzxd.as_mut() {
+                Some(lzxd) => output
+                    .extend_from_slice(lzxd.decompress_next(block, uncompressed_len as usize)?),
+                None if compressed_len == uncompressed_len => output.extend_from_slice(block),
+                None => return Err(CabError::InvalidData),
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Decompresses all the data of the folder with the given index.
+    pub fn decompress_folder(&self, index: usize) -> Result<Vec<u8>, CabError> {
+        self.decompress_folder_until(index, usize::MAX)
+    }
+
+    /// Extracts the contents of the given file, decompressing its folder up to the end of it.
+    pub fn extract_file(&self, file: &CabFile) -> Result<Vec<u8>, CabError> {
+        if file.folder >= FOLDER_CONTINUED {
+            return Err(CabError::SpansCabinets);
+        }
+
+        let start = file.offset as usize;
+        let end = start + file.size as usize;
+        let mut data = self.decompress_folder_until(file.folder as usize, end)?;
+        if data.len() < end {
+            return Err(CabError::InvalidData);
+        }
+        data.truncate(end);
+        data.drain(..start);
+        Ok(data)
+    }
+
+    /// Extracts the contents of the file with the given name, as found by [`Self::find`].
+    pub fn extract(&self, name: &str) -> Result<Vec<u8>, CabError> {
+        let file = self.find(name).ok_or(CabError::FileNotFound)?;
+        self.extract_file(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LzxdEncoder, MAX_CHUNK_SIZE};
+
+    /// A folder to build a cabinet with, and the data blocks of its compressed data, each
+    /// along with its uncompressed size.
+    struct TestFolder {
+        type_compress: u16,
+        blocks: Vec<(Vec<u8>, usize)>,
+    }
+
+    impl TestFolder {
+        fn uncompressed(data: &[u8]) -> Self {
+            Self {
+                type_compress: 0,
+                blocks: data
+                    .chunks(MAX_CHUNK_SIZE)
+                    .map(|chunk| (chunk.to_vec(), chunk.len()))
+                    .collect(),
+            }
+        }
+
+        fn lzx(data: &[u8], window_bits: u16) -> Self {
+            let window_size = WindowSize::from_value(1 << window_bits).unwrap();
+            let compressed = LzxdEncoder::new(window_size).compress_chunks(data);
+            Self {
+                type_compress: window_bits << 8 | 3,
+                blocks: compressed
+                    .into_iter()
+                    .zip(data.chunks(MAX_CHUNK_SIZE))
+                    .map(|(block, chunk)| (block, chunk.len()))
+                    .collect(),
+            }
+        }
+    }
+
+    /// Builds a cabinet with the given folders and files (by name, size, folder and offset),
+    /// with reserved areas of the given sizes in the header, folders and data blocks.
+    fn build(folders: &[TestFolder], files: &[(&str, u32, u16, u32)], reserve: [u8; 3]) -> Vec<u8> {
+        let has_reserve = reserve != [0; 3];
+        let header_len = 36
+            + if has_reserve {
+                4 + reserve[0] as usize
+            } else {
+                0
+            };
+        let folders_len = folders.len() * (8 + reserve[1] as usize);
+        let files_offset = header_len + folders_len;
+        let files_len = files
+            .iter()
+            .map(|(name, ..)| 16 + name.len() + 1)
+            .sum::<usize>();
+
+        let mut out = Vec::new();
+        out.extend(SIGNATURE);
+        out.extend(0u32.to_le_bytes());
+        out.extend(0u32.to_le_bytes()); // cbCabinet, not checked
+        out.extend(0u32.to_le_bytes());
+        out.extend((files_offset as u32).to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend([3, 1]);
+        out.extend((folders.len() as u16).to_le_bytes());
+        out.extend((files.len() as u16).to_le_bytes());
+        let flags = if has_reserve { FLAG_RESERVE_PRESENT } else { 0 };
+        out.extend(flags.to_le_bytes());
+        out.extend([0; 4]);
+        if has_reserve {
+            out.extend((reserve[0] as u16).to_le_bytes());
+            out.extend([reserve[1], reserve[2]]);
+            out.extend(vec![0xAA; reserve[0] as usize]);
+        }
+
+        let mut data_offset = files_offset + files_len;
+        for folder in folders {
+            out.extend((data_offset as u32).to_le_bytes());
+            out.extend((folder.blocks.len() as u16).to_le_bytes());
+            out.extend(folder.type_compress.to_le_bytes());
+            out.extend(vec![0xBB; reserve[1] as usize]);
+            data_offset += folder
+                .blocks
+                .iter()
+                .map(|(block, _)| 8 + reserve[2] as usize + block.len())
+                .sum::<usize>();
+        }
+
+        for &(name, size, folder, offset) in files {
+            out.extend(size.to_le_bytes());
+            out.extend(offset.to_le_bytes());
+            out.extend(folder.to_le_bytes());
+            out.extend([0; 6]);
+            out.extend(name.as_bytes());
+            out.push(0);
+        }
+
+        for folder in folders {
+            for (block, uncompressed_len) in &folder.blocks {
+                out.extend(0u32.to_le_bytes());
+                out.extend((block.len() as u16).to_le_bytes());
+                out.extend((*uncompressed_len as u16).to_le_bytes());
+                out.extend(vec![0xCC; reserve[2] as usize]);
+                out.extend(block);
+            }
+        }
+        out
+    }
+
+    fn file_data(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i % 97) as u8 ^ (i / 500) as u8 ^ seed)
+            .collect()
+    }
+
+    #[test]
+    fn extract_lzx_folder() {
+        let first = file_data(50_000, 1);
+        let second = file_data(40_000, 2);
+        let folder = [first.clone(), second.clone()].concat();
+        let data = build(
+            &[TestFolder::lzx(&folder, 16)],
+            &[
+                ("first.bin", first.len() as u32, 0, 0),
+                (
+                    "dir\\second.bin",
+                    second.len() as u32,
+                    0,
+                    first.len() as u32,
+                ),
+            ],
+            [0; 3],
+        );
+
+        let cabinet = Cabinet::new(&data).unwrap();
+        assert_eq!(
+            cabinet.folders()[0].compression(),
+            Compression::Lzx(WindowSize::KB64)
+        );
+        assert_eq!(cabinet.folders()[0].data_blocks(), 3);
+        assert_eq!(cabinet.files()[1].name(), b"dir\\second.bin");
+        assert_eq!(cabinet.extract("first.bin").unwrap(), first);
+        assert_eq!(cabinet.extract("DIR\\Second.bin").unwrap(), second);
+        assert_eq!(cabinet.decompress_folder(0).unwrap(), folder);
+    }
+
+    #[test]
+    fn extract_uncompressed_folder_with_reserve() {
+        let lzx = file_data(1000, 3);
+        let stored = file_data(40_000, 4);
+        let data = build(
+            &[TestFolder::lzx(&lzx, 21), TestFolder::uncompressed(&stored)],
+            &[("lzx", lzx.len() as u32, 0, 0), ("stored", 100, 1, 39_000)],
+            [5, 2, 3],
+        );
+
+        let cabinet = Cabinet::new(&data).unwrap();
+        assert_eq!(cabinet.folders()[1].compression(), Compression::None);
+        assert_eq!(cabinet.extract("lzx").unwrap(), lzx);
+        assert_eq!(cabinet.extract("stored").unwrap(), &stored[39_000..39_100]);
+    }
+
+    #[test]
+    fn compression_types() {
+        assert_eq!(
+            Compression::from_type(0x0F03),
+            Compression::Lzx(WindowSize::KB32)
+        );
+        assert_eq!(
+            Compression::from_type(0x1503),
+            Compression::Lzx(WindowSize::MB2)
+        );
+        assert_eq!(Compression::from_type(0x1603), Compression::Unknown(0x1603));
+        assert_eq!(Compression::from_type(0x0E03), Compression::Unknown(0x0E03));
+        assert_eq!(Compression::from_type(0x0001), Compression::MsZip);
+        assert_eq!(Compression::from_type(0x0F02), Compression::Quantum);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Cabinet::new(b"MSCB").err(),
+            Some(CabError::InvalidSignature)
+        );
+        assert_eq!(Cabinet::new(b"MSCF").err(), Some(CabError::UnexpectedEof));
+
+        let data = build(
+            &[TestFolder {
+                type_compress: 1,
+                blocks: vec![(vec![0; 10], 10)],
+            }],
+            &[("zip", 10, 0, 0), ("next", 10, 0xFFFE, 0)],
+            [0; 3],
+        );
+        let cabinet = Cabinet::new(&data).unwrap();
+        assert_eq!(cabinet.extract("missing"), Err(CabError::FileNotFound));
+        assert_eq!(
+            cabinet.extract("zip"),
+            Err(CabError::UnsupportedCompression(Compression::MsZip))
+        );
+        assert_eq!(cabinet.extract("next"), Err(CabError::SpansCabinets));
+
+        let data = build(
+            &[TestFolder::uncompressed(b"short")],
+            &[("long", 10, 0, 0)],
+            [0; 3],
+        );
+        let cabinet = Cabinet::new(&data).unwrap();
+        assert_eq!(cabinet.extract("long"), Err(CabError::InvalidData));
+    }
+}
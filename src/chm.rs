@@ -0,0 +1,739 @@
+//! Reading of Microsoft Compiled HTML Help (CHM) files.
+//!
+//! A CHM file (also known by its ITSF signature) has a directory listing the files it holds,
+//! which are either stored as-is in the uncompressed section, or in the compressed one. The
+//! latter is a single LZX data stream holding all the compressed files one after the other,
+//! whose format is described by its `ControlData` file.
+//!
+//! The LZX data stream is split into frames of [`MAX_CHUNK_SIZE`] bytes once decompressed,
+//! like chunks, and its state is reset every few frames, after which the data is compressed
+//! anew (although E8 translation keeps counting positions from the start of the data). The
+//! `ResetTable` file tells where every frame starts in the compressed data, so any
+//! range of the data can be read by decoding from the last reset point before it.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use ::lzxd::chm::Chm;
+//!
+//! let data = std::fs::read("help.chm")?;
+//! let chm = Chm::new(&data)?;
+//! for entry in chm.entries() {
+//!     println!("{} ({} bytes)", String::from_utf8_lossy(entry.name()), entry.length());
+//! }
+//! let index = chm.extract("/index.html")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`MAX_CHUNK_SIZE`]: ../constant.MAX_CHUNK_SIZE.html
+use alloc::vec::Vec;
+use core::{fmt, ops::Range};
+
+use crate::bytes::ByteReader;
+use crate::{DecompressError, Lzxd, WindowSize, MAX_CHUNK_SIZE};
+
+/// Magic bytes at the start of every CHM file.
+const ITSF_SIGNATURE: &[u8; 4] = b"ITSF";
+
+/// Magic bytes at the start of the directory.
+const ITSP_SIGNATURE: &[u8; 4] = b"ITSP";
+
+/// Magic bytes at the start of the directory chunks listing files.
+const PMGL_SIGNATURE: &[u8; 4] = b"PMGL";
+
+/// Magic bytes of the control data of the compressed section.
+const LZXC_SIGNATURE: &[u8; 4] = b"LZXC";
+
+/// The file with the compressed data of the compressed section.
+const CONTENT: &str = "::DataSpace/Storage/MSCompressed/Content";
+
+/// The file describing how the compressed section was compressed.
+const CONTROL_DATA: &str = "::DataSpace/Storage/MSCompressed/ControlData";
+
+/// The file with the offsets of the frames in the compressed data of the compressed section.
+const RESET_TABLE: &str = "::DataSpace/Storage/MSCompressed/Transform/\
+    {7FC28940-9D31-11D0-9B27-00A0C91E9C7C}/InstanceData/ResetTable";
+
+/// The error type used when a CHM file cannot be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChmError {
+    /// The data does not start with the signature of a CHM file, or one of its parts doesn't
+    /// have the expected signature.
+    InvalidSignature,
+
+    /// The data ended before the file was fully read.
+    UnexpectedEof,
+
+    /// The file contains a value that doesn't make sense, such as a window size not supported
+    /// by LZX.
+    InvalidData,
+
+    /// The file has no entry with the requested name.
+    FileNotFound,
+
+    /// The compressed data could not be decompressed.
+    Decompress(DecompressError),
+}
+
+impl fmt::Display for ChmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ChmError::*;
+
+        match self {
+            InvalidSignature => write!(f, "data is not a CHM file"),
+            UnexpectedEof => write!(f, "reached end of data without fully reading the CHM file"),
+            InvalidData => write!(f, "CHM file contains invalid data"),
+            FileNotFound => write!(f, "file not found in the CHM file"),
+            Decompress(e) => write!(f, "failed to decompress CHM content: {}", e),
+        }
+    }
+}
+
+impl core::error::Error for ChmError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Decompress(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecompressError> for ChmError {
+    fn from(e: DecompressError) -> Self {
+        Self::Decompress(e)
+    }
+}
+
+/// An entry of the directory of a CHM file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChmEntry<'a> {
+    name: &'a [u8],
+    section: u64,
+    offset: u64,
+    length: u64,
+}
+
+impl<'a> ChmEntry<'a> {
+    /// The name of the entry, encoded as UTF-8. Names starting with a slash are the files of
+    /// the help itself, and those ending with one are directories.
+    pub fn name(&self) -> &'a [u8] {
+        self.name
+    }
+
+    /// The section the entry is stored in, which is 0 for the uncompressed section and 1 for
+    /// the compressed one.
+    pub fn section(&self) -> u64 {
+        self.section
+    }
+
+    /// The offset of the entry in the data of its section.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The length of the entry.
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// The range of its section holding the given range of the contents of the entry.
+    fn section_range(&self, range: Range<u64>) -> Result<Range<u64>, ChmError> {
+        match (
+            self.offset.checked_add(range.start),
+            self.offset.checked_add(range.end),
+        ) {
+            (Some(start), Some(end)) => Ok(start..end),
+            _ => Err(ChmError::InvalidData),
+        }
+    }
+}
+
+/// The compressed section of a CHM file.
+#[derive(Debug, Clone)]
+struct LzxSection<'a> {
+    /// The compressed data.
+    content: &'a [u8],
+
+    window_size: WindowSize,
+
+    /// How many frames there are between reset points.
+    reset_frames: usize,
+
+    /// The offset in `content` where every frame starts.
+    frames: Vec<usize>,
+
+    /// The length of the data once decompressed.
+    uncompressed_len: u64,
+}
+
+impl<'a> LzxSection<'a> {
+    /// Reads the control data and reset table of the section with the given `content`.
+    fn new(content: &'a [u8], control_data: &[u8], reset_table: &[u8]) -> Result<Self, ChmError> {
+        let mut reader = ByteReader::new(control_data, ChmError::UnexpectedEof);
+        reader.read_u32()?; // number of values following
+        if reader.read(4)? != LZXC_SIGNATURE {
+            return Err(ChmError::InvalidSignature);
+        }
+        let version = reader.read_u32()?;
+        let mut reset_interval = reader.read_u32()? as usize;
+        let mut window_size = reader.read_u32()? as usize;
+
+        // The second version gives them in frames rather than bytes.
+        if version == 2 {
+            reset_interval *= MAX_CHUNK_SIZE;
+            window_size *= MAX_CHUNK_SIZE;
+        }
+        let window_size = WindowSize::from_value(window_size).ok_or(ChmError::InvalidData)?;
+        if reset_interval == 0 || reset_interval % MAX_CHUNK_SIZE != 0 {
+            return Err(ChmError::InvalidData);
+        }
+
+        let mut reader = ByteReader::new(reset_table, ChmError::UnexpectedEof);
+        reader.read_u32()?; // version
+        let entry_count = reader.read_u32()? as usize;
+        let entry_size = reader.read_u32()? as usize;
+        let table_offset = reader.read_u32()? as usize;
+        let uncompressed_len = reader.read_u64()?;
+        reader.read_u64()?; // compressed length
+        if reader.read_u64()? != MAX_CHUNK_SIZE as u64 || entry_size != 8 {
+            return Err(ChmError::InvalidData);
+        }
+
+        reader.seek(table_offset);
+        let frames = (0..entry_count)
+            .map(|_| {
+                let offset = reader.read_u64()?;
+                match usize::try_from(offset) {
+                    Ok(offset) if offset <= content.len() => Ok(offset),
+                    _ => Err(ChmError::InvalidData),
+                }
+            })
+            .collect::<Result<Vec<_>, ChmError>>()?;
+        if (frames.len() as u64) < uncompressed_len.div_ceil(MAX_CHUNK_SIZE as u64) {
+            return Err(ChmError::InvalidData);
+        }
+
+        Ok(Self {
+            content,
+            window_size,
+            reset_frames: reset_interval / MAX_CHUNK_SIZE,
+            frames,
+            uncompressed_len,
+        })
+    }
+
+    /// Decompresses the given range of the data, starting at the last reset point before it.
+    fn decompress(&self, range: Range<u64>) -> Result<Vec<u8>, ChmError> {
+        if range.end > self.uncompressed_len {
+            return Err(ChmError::InvalidData);
+        }
+
+        let frame_len = MAX_CHUNK_SIZE as u64;
+        let first_frame = (range.start / frame_len) as usize;
+        let first_frame = first_frame - first_frame % self.reset_frames;
+        let end_frame = range.end.div_ceil(frame_len) as usize;
+
+        let mut output = Vec::with_capacity((range.end - range.start) as usize);
+        let mut lzxd = Lzxd::new(self.window_size);
+        for frame in first_frame..end_frame {
+            if frame % self.reset_frames == 0 {
+                // E8 translation still refers to positions since the start of the data.
+                lzxd.reset_at(frame * MAX_CHUNK_SIZE);
+            }
+
+            let start = self.frames[frame];
+            let end = self.frames.get(frame + 1).copied();
+            let chunk = self
+                .content
+                .get(start..end.unwrap_or(self.content.len()))
+                .ok_or(ChmError::InvalidData)?;
+
+            let frame_start = frame as u64 * frame_len;
+            let frame_end = (frame_start + frame_len).min(self.uncompressed_len);
+            let decompressed = lzxd.decompress_next(chunk, (frame_end - frame_start) as usize)?;
+
+            let from = range.start.max(frame_start) - frame_start;
+            let to = range.end.min(frame_end) - frame_start;
+            if from < to {
+                output.extend_from_slice(&decompressed[from as usize..to as usize]);
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+/// A CHM file, read from a buffer holding all of its data.
+#[derive(Debug, Clone)]
+pub struct Chm<'a> {
+    /// The data of the uncompressed section.
+    uncompressed: &'a [u8],
+    entries: Vec<ChmEntry<'a>>,
+    /// The compressed section, if any.
+    compressed: Option<LzxSection<'a>>,
+}
+
+/// Reads an integer encoded with 7 bits per byte, most-significant first, where the high bit
+/// of every byte but the last is set.
+fn read_encint(reader: &mut ByteReader<ChmError>) -> Result<u64, ChmError> {
+    let mut value = 0u64;
+    loop {
+        let byte = reader.read_u8()?;
+        if value >> 57 != 0 {
+            return Err(ChmError::InvalidData);
+        }
+        value = value << 7 | (byte & 0x7F) as u64;
+        if byte & 0x80 == 0 {
+            break Ok(value);
+        }
+    }
+}
+
+impl<'a> Chm<'a> {
+    /// Reads the headers and directory of the CHM file, along with the control data and reset
+    /// table of its compressed section.
+    pub fn new(data: &'a [u8]) -> Result<Self, ChmError> {
+        let mut reader = ByteReader::new(data, ChmError::UnexpectedEof);
+        if reader.read(4)? != ITSF_SIGNATURE {
+            return Err(ChmError::InvalidSignature);
+        }
+        let version = reader.read_u32()?;
+        reader.seek(0x48);
+        let dir_offset = reader.read_u64()? as usize;
+        let dir_len = reader.read_u64()? as usize;
+        // Older versions have the uncompressed section right after the directory.
+        let content_offset = if version >= 3 {
+            reader.read_u64()? as usize
+        } else {
+            dir_offset.saturating_add(dir_len)
+        };
+        let uncompressed = data.get(content_offset..).ok_or(ChmError::UnexpectedEof)?;
+
+        reader.seek(dir_offset);
+        if reader.read(4)? != ITSP_SIGNATURE {
+            return Err(ChmError::InvalidSignature);
+        }
+        reader.read_u32()?; // version
+        let header_len = reader.read_u32()? as usize;
+        reader.read_u32()?; // unknown
+        let chunk_len = reader.read_u32()? as usize;
+        reader.seek(dir_offset + 0x2C);
+        let chunk_count = reader.read_u32()? as usize;
+
+        // Only the listing chunks matter, the index chunks are there to speed up lookups.
+        let mut entries = Vec::new();
+        for chunk in 0..chunk_count {
+            let chunk_start = dir_offset + header_len + chunk * chunk_len;
+            reader.seek(chunk_start);
+            let chunk_data = reader.read(chunk_len)?;
+            if &chunk_data[..4.min(chunk_len)] != PMGL_SIGNATURE {
+                continue;
+            }
+
+            let mut reader = ByteReader::new(chunk_data, ChmError::UnexpectedEof);
+            reader.seek(4);
+            let free_space = reader.read_u32()? as usize;
+            let entries_end = chunk_len
+                .checked_sub(free_space)
+                .ok_or(ChmError::InvalidData)?;
+            let mut reader = ByteReader::new(&chunk_data[..entries_end], ChmError::UnexpectedEof);
+            reader.seek(0x14);
+            while !reader.is_at_end() {
+                let name_len = read_encint(&mut reader)?;
+                entries.push(ChmEntry {
+                    name: reader.read(name_len as usize)?,
+                    section: read_encint(&mut reader)?,
+                    offset: read_encint(&mut reader)?,
+                    length: read_encint(&mut reader)?,
+                });
+            }
+        }
+
+        let mut this = Self {
+            uncompressed,
+            entries,
+            compressed: None,
+        };
+        let section_data = |name| {
+            this.find(name)
+                .filter(|entry| entry.section == 0)
+                .map(|entry| {
+                    let range = entry.section_range(0..entry.length)?;
+                    this.uncompressed_range(range)
+                })
+        };
+        this.compressed = match (
+            section_data(CONTENT),
+            section_data(CONTROL_DATA),
+            section_data(RESET_TABLE),
+        ) {
+            (Some(content), Some(control_data), Some(reset_table)) => {
+                Some(LzxSection::new(content?, control_data?, reset_table?)?)
+            }
+            _ => None,
+        };
+        Ok(this)
+    }
+
+    /// The entries of the directory, in the order they're listed.
+    pub fn entries(&self) -> &[ChmEntry<'a>] {
+        &self.entries
+    }
+
+    /// Finds the entry with the given name, ignoring the case of ASCII letters.
+    pub fn find(&self, name: &str) -> Option<&ChmEntry<'a>> {
+        self.entries
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name.as_bytes()))
+    }
+
+    /// The given range of the uncompressed section.
+    fn uncompressed_range(&self, range: Range<u64>) -> Result<&'a [u8], ChmError> {
+        let start = usize::try_from(range.start).map_err(|_| ChmError::UnexpectedEof)?;
+        let end = usize::try_from(range.end).map_err(|_| ChmError::UnexpectedEof)?;
+        self.uncompressed
+            .get(start..end)
+            .ok_or(ChmError::UnexpectedEof)
+    }
+
+    /// Reads the given range of the contents of the entry, decompressing only as much of the
+    /// compressed section as needed if it's stored there.
+    pub fn read_range(&self, entry: &ChmEntry, range: Range<u64>) -> Result<Vec<u8>, ChmError> {
+        if range.start > range.end || range.end > entry.length {
+            return Err(ChmError::InvalidData);
+        }
+
+        let range = entry.section_range(range)?;
+        match entry.section {
+            0 => self.uncompressed_range(range).map(<[u8]>::to_vec),
+            1 => self
+                .compressed
+                .as_ref()
+                .ok_or(ChmError::FileNotFound)?
+                .decompress(range),
+            _ => Err(ChmError::InvalidData),
+        }
+    }
+
+    /// Reads the contents of the entry.
+    pub fn read(&self, entry: &ChmEntry) -> Result<Vec<u8>, ChmError> {
+        self.read_range(entry, 0..entry.length)
+    }
+
+    /// Reads the contents of the entry with the given name, as found by [`Self::find`].
+    pub fn extract(&self, name: &str) -> Result<Vec<u8>, ChmError> {
+        let entry = self.find(name).ok_or(ChmError::FileNotFound)?;
+        self.read(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LzxdEncoder;
+
+    const WINDOW_SIZE: WindowSize = WindowSize::KB64;
+    const CHUNK_LEN: usize = 0x1000;
+
+    fn encint(mut value: u64) -> Vec<u8> {
+        let mut out = vec![(value & 0x7F) as u8];
+        value >>= 7;
+        while value != 0 {
+            out.push((value & 0x7F) as u8 | 0x80);
+            value >>= 7;
+        }
+        out.reverse();
+        out
+    }
+
+    /// Compresses the data in frames, starting anew every `reset_frames` of them, and returns
+    /// the compressed data along with the offset of every frame in it.
+    fn compress(
+        data: &[u8],
+        reset_frames: usize,
+        e8_translation_size: Option<u32>,
+    ) -> (Vec<u8>, Vec<u64>) {
+        let mut compressed = Vec::new();
+        let mut frames = Vec::new();
+        let mut encoder = LzxdEncoder::new(WINDOW_SIZE);
+        encoder.set_e8_translation(e8_translation_size);
+        for (i, frame) in data.chunks(MAX_CHUNK_SIZE).enumerate() {
+            if i % reset_frames == 0 {
+                encoder.reset_at(i * MAX_CHUNK_SIZE);
+            }
+            frames.push(compressed.len() as u64);
+            compressed.extend(encoder.compress_next(frame).unwrap());
+        }
+        (compressed, frames)
+    }
+
+    /// Builds a CHM file with the given `stored` files in the uncompressed section and the
+    /// `compressed` files (by name, offset and length) in the compressed one, whose data is
+    /// `content` and uses the given `control_data`.
+    fn build(
+        stored: &[(&str, &[u8])],
+        compressed: &[(&str, u64, u64)],
+        content: &[u8],
+        control_data: &[u8],
+    ) -> Vec<u8> {
+        build_with_e8(None, stored, compressed, content, control_data)
+    }
+
+    /// Builds a CHM file like [`build`] does, compressing the `content` with E8 translation if
+    /// a translation size is given.
+    fn build_with_e8(
+        e8_translation_size: Option<u32>,
+        stored: &[(&str, &[u8])],
+        compressed: &[(&str, u64, u64)],
+        content: &[u8],
+        control_data: &[u8],
+    ) -> Vec<u8> {
+        let reset_frames = u32::from_le_bytes(control_data[12..16].try_into().unwrap()) as usize;
+        let reset_frames = if control_data[8] == 2 {
+            reset_frames
+        } else {
+            reset_frames / MAX_CHUNK_SIZE
+        };
+        let (lzx, frames) = compress(content, reset_frames, e8_translation_size);
+        let mut reset_table = Vec::new();
+        reset_table.extend(2u32.to_le_bytes());
+        reset_table.extend((frames.len() as u32).to_le_bytes());
+        reset_table.extend(8u32.to_le_bytes());
+        reset_table.extend(0x28u32.to_le_bytes());
+        reset_table.extend((content.len() as u64).to_le_bytes());
+        reset_table.extend((lzx.len() as u64).to_le_bytes());
+        reset_table.extend((MAX_CHUNK_SIZE as u64).to_le_bytes());
+        frames
+            .iter()
+            .for_each(|offset| reset_table.extend(offset.to_le_bytes()));
+
+        let mut section: Vec<u8> = Vec::new();
+        let mut chunk = PMGL_SIGNATURE.to_vec();
+        chunk.extend([0; 16]);
+        let mut add_entry = |name: &str, section_index: u64, offset: u64, len: u64| {
+            chunk.extend(encint(name.len() as u64));
+            chunk.extend(name.as_bytes());
+            chunk.extend(encint(section_index));
+            chunk.extend(encint(offset));
+            chunk.extend(encint(len));
+        };
+        for (name, data) in [
+            (CONTENT, &lzx[..]),
+            (CONTROL_DATA, control_data),
+            (RESET_TABLE, &reset_table),
+        ]
+        .into_iter()
+        .chain(stored.iter().copied())
+        {
+            add_entry(name, 0, section.len() as u64, data.len() as u64);
+            section.extend(data);
+        }
+        for &(name, offset, len) in compressed {
+            add_entry(name, 1, offset, len);
+        }
+        let free_space = (CHUNK_LEN - chunk.len()) as u32;
+        chunk[4..8].copy_from_slice(&free_space.to_le_bytes());
+        chunk.resize(CHUNK_LEN, 0);
+
+        let mut directory = ITSP_SIGNATURE.to_vec();
+        directory.extend(1u32.to_le_bytes());
+        directory.extend(0x54u32.to_le_bytes());
+        directory.extend(10u32.to_le_bytes());
+        directory.extend((CHUNK_LEN as u32).to_le_bytes());
+        directory.resize(0x2C, 0);
+        directory.extend(1u32.to_le_bytes());
+        directory.resize(0x54, 0);
+        directory.extend(chunk);
+
+        let mut out = ITSF_SIGNATURE.to_vec();
+        out.extend(3u32.to_le_bytes());
+        out.extend(0x60u32.to_le_bytes());
+        out.resize(0x48, 0);
+        out.extend(0x60u64.to_le_bytes());
+        out.extend((directory.len() as u64).to_le_bytes());
+        out.extend((0x60 + directory.len() as u64).to_le_bytes());
+        out.extend(directory);
+        out.extend(section);
+        out
+    }
+
+    /// Control data of the second version, with the reset interval and window size in frames.
+    fn control_data(reset_frames: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(6u32.to_le_bytes());
+        out.extend(LZXC_SIGNATURE);
+        out.extend(2u32.to_le_bytes());
+        out.extend(reset_frames.to_le_bytes());
+        out.extend((WINDOW_SIZE.value() as u32 / MAX_CHUNK_SIZE as u32).to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out.extend(0u32.to_le_bytes());
+        out
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i % 241) as u8 ^ (i / 900) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn read_both_sections() {
+        let content = content(5 * MAX_CHUNK_SIZE + 1000);
+        let data = build(
+            &[("/stored.txt", b"stored as-is")],
+            &[
+                ("/index.html", 0, 100_000),
+                ("/page.html", 100_000, content.len() as u64 - 100_000),
+            ],
+            &content,
+            &control_data(2),
+        );
+
+        let chm = Chm::new(&data).unwrap();
+        assert_eq!(chm.entries().len(), 6);
+        assert_eq!(chm.extract("/stored.txt").unwrap(), b"stored as-is");
+        assert_eq!(chm.extract("/INDEX.html").unwrap(), &content[..100_000]);
+        assert_eq!(chm.extract("/page.html").unwrap(), &content[100_000..]);
+
+        let entry = chm.find("/page.html").unwrap();
+        assert_eq!(entry.section(), 1);
+        assert_eq!(
+            chm.read_range(entry, 1000..60_000).unwrap(),
+            &content[101_000..160_000]
+        );
+    }
+
+    #[test]
+    fn seek_to_reset_point() {
+        let content = content(6 * MAX_CHUNK_SIZE);
+        let mut data = build(
+            &[],
+            &[("/all", 0, content.len() as u64)],
+            &content,
+            &control_data(2),
+        );
+
+        // Break the first frames, which only matter to data before the first reset point.
+        let chm = Chm::new(&data).unwrap();
+        let lzx = chm.compressed.as_ref().unwrap();
+        let corrupt = lzx.content.as_ptr() as usize - data.as_ptr() as usize + 10;
+        let second_reset = lzx.frames[2];
+        data[corrupt..corrupt + second_reset - 20].fill(0xFF);
+
+        let chm = Chm::new(&data).unwrap();
+        let entry = chm.find("/all").unwrap();
+        let start = 2 * MAX_CHUNK_SIZE as u64 + 5;
+        assert_eq!(
+            chm.read_range(entry, start..start + 70_000).unwrap(),
+            &content[start as usize..start as usize + 70_000]
+        );
+        assert_ne!(
+            chm.read_range(entry, 0..100).ok().as_deref(),
+            Some(&content[..100])
+        );
+    }
+
+    #[test]
+    fn e8_translation_across_resets() {
+        // Code-like content, with a CALL instruction to a nearby function every few bytes.
+        let mut content = Vec::new();
+        while content.len() < 5 * MAX_CHUNK_SIZE + 1000 {
+            content.extend(b"\x55\x8B\xEC\xE8");
+            content.extend(((content.len() % 5000) as i32).to_le_bytes());
+        }
+        let data = build_with_e8(
+            Some(12_000_000),
+            &[],
+            &[("/all", 0, content.len() as u64)],
+            &content,
+            &control_data(2),
+        );
+
+        // Positions keep counting from the start of the data after a reset point.
+        let chm = Chm::new(&data).unwrap();
+        let entry = chm.find("/all").unwrap();
+        let start = 3 * MAX_CHUNK_SIZE as u64 + 100;
+        assert_eq!(
+            chm.read_range(entry, start..start + 40_000).unwrap(),
+            &content[start as usize..start as usize + 40_000]
+        );
+        assert_eq!(chm.read(entry).unwrap(), content);
+    }
+
+    #[test]
+    fn control_data_in_bytes() {
+        let mut control_data = control_data(0);
+        control_data[8] = 1;
+        control_data[12..16].copy_from_slice(&(3 * MAX_CHUNK_SIZE as u32).to_le_bytes());
+        control_data[16..20].copy_from_slice(&(WINDOW_SIZE.value() as u32).to_le_bytes());
+
+        let content = content(4 * MAX_CHUNK_SIZE);
+        let data = build(
+            &[],
+            &[("/all", 0, content.len() as u64)],
+            &content,
+            &control_data,
+        );
+        let chm = Chm::new(&data).unwrap();
+        assert_eq!(chm.compressed.as_ref().unwrap().reset_frames, 3);
+        assert_eq!(chm.extract("/all").unwrap(), content);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Chm::new(b"ITSP").err(), Some(ChmError::InvalidSignature));
+        assert_eq!(Chm::new(b"ITSF").err(), Some(ChmError::UnexpectedEof));
+
+        let data = build(&[], &[("/a", 0, 10)], b"0123456789", &control_data(2));
+        let chm = Chm::new(&data).unwrap();
+        assert_eq!(chm.extract("/b"), Err(ChmError::FileNotFound));
+        let entry = chm.find("/a").unwrap();
+        assert_eq!(chm.read_range(entry, 5..11), Err(ChmError::InvalidData));
+        assert_eq!(chm.read_range(entry, 5..10).unwrap(), b"56789");
+
+        let mut control_data = control_data(2);
+        control_data[16] = 3;
+        let data = build(&[], &[], b"", &control_data);
+        assert_eq!(Chm::new(&data).err(), Some(ChmError::InvalidData));
+    }
+
+    #[test]
+    fn overflowing_offsets() {
+        let data = build(&[], &[("/a", 0, 10)], b"0123456789", &control_data(2));
+        let chm = Chm::new(&data).unwrap();
+        let entry = ChmEntry {
+            offset: u64::MAX - 5,
+            ..*chm.find("/a").unwrap()
+        };
+        assert_eq!(chm.read_range(&entry, 0..10), Err(ChmError::InvalidData));
+
+        // Rename the entry of the compressed data and add another one after the last entry,
+        // whose offset and length overflow once added.
+        let mut data = data;
+        let chunk_start = 0x60 + 0x54;
+        data[chunk_start + 0x15] = b'_';
+        let free_space = &data[chunk_start + 4..chunk_start + 8];
+        let free_space = u32::from_le_bytes(free_space.try_into().unwrap()) as usize;
+        let mut entry = encint(CONTENT.len() as u64);
+        entry.extend(CONTENT.as_bytes());
+        [0, u64::MAX - 1, 2]
+            .iter()
+            .for_each(|&value| entry.extend(encint(value)));
+        let entries_end = chunk_start + CHUNK_LEN - free_space;
+        data[entries_end..entries_end + entry.len()].copy_from_slice(&entry);
+        data[chunk_start + 4..chunk_start + 8]
+            .copy_from_slice(&((free_space - entry.len()) as u32).to_le_bytes());
+        assert_eq!(Chm::new(&data).err(), Some(ChmError::InvalidData));
+    }
+
+    #[test]
+    fn read_encints() {
+        let data = [0x05, 0x81, 0x00, 0xFF, 0x7F, 0x80];
+        let mut reader = ByteReader::new(&data[..], ChmError::UnexpectedEof);
+        assert_eq!(read_encint(&mut reader), Ok(5));
+        assert_eq!(read_encint(&mut reader), Ok(128));
+        assert_eq!(read_encint(&mut reader), Ok(0x3FFF));
+        assert_eq!(read_encint(&mut reader), Err(ChmError::UnexpectedEof));
+        assert_eq!(encint(0x3FFF), [0xFF, 0x7F]);
+    }
+}
@@ -0,0 +1,1535 @@
+use alloc::{borrow::Cow, vec, vec::Vec};
+use core::{fmt, mem};
+
+use crate::block::{BASE_POSITION, FOOTER_BITS};
+use crate::matchfinder::{BinaryTree, HashChain, Limits, Match, MatchFinder, MIN_MATCH};
+use crate::optimal::{self, Costs, MatchTable};
+use crate::split::{self, BlockCost, BlockStats, BlockTrees};
+use crate::{
+    huffman, BitWriter, BlockType, Lzxd, Variant, WindowSize, MAX_CHUNK_SIZE,
+    WIM_DEFAULT_BLOCK_SIZE, WIM_E8_TRANSLATION_SIZE,
+};
+
+/// The longest match that can be encoded without an Extra Length field.
+pub(crate) const MAX_MATCH: usize = 257;
+
+/// > Each tree element can have a path length of [0, 16]
+pub(crate) const MAX_PATH_LENGTH: u8 = 16;
+
+/// The pretree path lengths are output using 4 bits each.
+const MAX_PRETREE_PATH_LENGTH: u8 = 15;
+
+/// Matches of the shortest length farther than this are not worth using.
+const FAR_OFFSET: u32 = 8 * 1024;
+
+/// The error type used when compression fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressError {
+    /// Tried to compress a chunk longer than [`MAX_CHUNK_SIZE`].
+    ///
+    /// [`MAX_CHUNK_SIZE`]: constant.MAX_CHUNK_SIZE.html
+    ChunkTooLong,
+
+    /// The reference data, rounded up to a multiple of [`MAX_CHUNK_SIZE`], does not fit in
+    /// the window.
+    ///
+    /// [`MAX_CHUNK_SIZE`]: constant.MAX_CHUNK_SIZE.html
+    WindowTooSmall,
+}
+
+impl fmt::Display for CompressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ChunkTooLong => write!(
+                f,
+                "tried compressing a chunk longer than {} bytes",
+                MAX_CHUNK_SIZE
+            ),
+            Self::WindowTooSmall => write!(f, "the reference data does not fit in the window"),
+        }
+    }
+}
+
+impl core::error::Error for CompressError {}
+
+/// How hard the encoder tries to compress the data, from 1 (fastest) to 9 (smallest output).
+///
+/// Lower levels look up previous occurrences of the data in hash chains, while higher levels
+/// use binary trees, which find longer matches in less time than a hash chain would need to
+/// find them. The two highest levels also price every way to encode the data with the
+/// Huffman trees it would produce, choosing the cheapest one (near-optimal parsing), instead
+/// of taking the longest match at every position.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CompressionLevel(u8);
+
+/// The settings of a compression level.
+struct LevelParams {
+    /// Whether to use a binary tree to find matches instead of a hash chain.
+    binary_tree: bool,
+
+    /// How many previous occurrences to check at most.
+    depth: usize,
+
+    /// A match this long is good enough to stop looking for longer ones.
+    nice_len: usize,
+
+    /// How to choose between the matches found.
+    parser: Parser,
+}
+
+/// The ways the encoder can choose between the matches found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Parser {
+    /// Take the longest match at every position.
+    Greedy,
+
+    /// Check if a longer match starts at the next byte before taking a match.
+    Lazy,
+
+    /// Find the cheapest way to encode the whole chunk, refining the costs with the given
+    /// number of passes.
+    Optimal { passes: usize },
+}
+
+impl CompressionLevel {
+    /// The fastest level.
+    pub const FASTEST: Self = Self(1);
+
+    /// The level used by default, which balances speed and compression.
+    pub const DEFAULT: Self = Self(6);
+
+    /// The level that produces the smallest output.
+    pub const BEST: Self = Self(9);
+
+    /// The compression level with the given number, if it's between 1 and 9.
+    pub fn new(level: u8) -> Option<Self> {
+        (1..=9).contains(&level).then_some(Self(level))
+    }
+
+    /// The number of this compression level, between 1 and 9.
+    pub fn get(self) -> u8 {
+        self.0
+    }
+
+    fn params(self) -> LevelParams {
+        let (binary_tree, depth, nice_len, parser) = match self.0 {
+            1 => (false, 4, 16, Parser::Greedy),
+            2 => (false, 8, 32, Parser::Greedy),
+            3 => (false, 16, 32, Parser::Lazy),
+            4 => (false, 32, 64, Parser::Lazy),
+            5 => (false, 64, 128, Parser::Lazy),
+            6 => (true, 32, 64, Parser::Lazy),
+            7 => (true, 64, 128, Parser::Lazy),
+            8 => (true, 64, 128, Parser::Optimal { passes: 2 }),
+            _ => (true, 128, 257, Parser::Optimal { passes: 4 }),
+        };
+        LevelParams {
+            binary_tree,
+            depth,
+            nice_len,
+            parser,
+        }
+    }
+
+    fn match_finder(self) -> MatchFinder {
+        let params = self.params();
+        if params.binary_tree {
+            MatchFinder::BinaryTree(BinaryTree::new(params.depth, params.nice_len))
+        } else {
+            MatchFinder::HashChain(HashChain::new(params.depth, params.nice_len))
+        }
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A single element of the token sequence of a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Token {
+    Literal(u8),
+    Match {
+        length: u32,
+        position_slot: u16,
+        /// The verbatim bits following the position slot, if any.
+        footer: u32,
+    },
+}
+
+impl Token {
+    /// The main tree element and, for long matches, the length tree element of the token.
+    pub(crate) fn elements(self) -> (usize, Option<usize>) {
+        match self {
+            Token::Literal(value) => (value as usize, None),
+            Token::Match {
+                length,
+                position_slot,
+                ..
+            } => {
+                let length_header = (length as usize - 2).min(7);
+                let main_element = 256 + 8 * position_slot as usize + length_header;
+                let length_element = (length_header == 7).then(|| (length as usize - 9).min(248));
+                (main_element, length_element)
+            }
+        }
+    }
+
+    /// How many bytes the token decompresses into.
+    pub(crate) fn len(self) -> usize {
+        match self {
+            Token::Literal(_) => 1,
+            Token::Match { length, .. } => length as usize,
+        }
+    }
+
+    /// Updates the repeated offsets after the token, as the decoder does.
+    fn update_repeated_offsets(self, r: &mut [u32; 3]) {
+        match self {
+            Token::Literal(_) => {}
+            Token::Match {
+                position_slot: 0, ..
+            } => {}
+            Token::Match {
+                position_slot: slot @ (1 | 2),
+                ..
+            } => r.swap(0, slot as usize),
+            Token::Match {
+                position_slot,
+                footer,
+                ..
+            } => {
+                r[2] = r[1];
+                r[1] = r[0];
+                r[0] = BASE_POSITION[position_slot as usize] + footer - 2;
+            }
+        }
+    }
+
+    /// The aligned offset tree element of the token, for matches with enough footer bits to
+    /// use it in aligned offset blocks.
+    pub(crate) fn aligned_element(self) -> Option<usize> {
+        match self {
+            Token::Match {
+                position_slot,
+                footer,
+                ..
+            } if position_slot >= 3 && FOOTER_BITS[position_slot as usize] >= 3 => {
+                Some((footer & 7) as usize)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The codes of the trees of a compressed block, used to write its tokens.
+struct BlockCodes<'a> {
+    trees: &'a BlockTrees,
+    main: Vec<u16>,
+    length: Vec<u16>,
+    /// The aligned offset tree codes, if it's an aligned offset block.
+    aligned: Option<Vec<u16>>,
+}
+
+impl<'a> BlockCodes<'a> {
+    fn new(cost: &'a BlockCost) -> Self {
+        let trees = &cost.trees;
+        Self {
+            trees,
+            main: huffman::codes(&trees.main),
+            length: huffman::codes(&trees.length),
+            aligned: (cost.block_type == BlockType::AlignedOffset)
+                .then(|| huffman::codes(trees.aligned.as_ref().unwrap())),
+        }
+    }
+}
+
+/// The main interface to perform LZXD compression.
+///
+/// This structure stores the required state to produce the compressed chunks of data in a
+/// sequential order, which can then be decompressed with an [`Lzxd`] decoder using the same
+/// [`WindowSize`] and [`Variant`].
+///
+/// ```
+/// use ::lzxd::{Lzxd, LzxdEncoder, WindowSize};
+///
+/// let data = b"Hello, world! Hello, world! Hello, world!";
+///
+/// let mut encoder = LzxdEncoder::new(WindowSize::KB64);
+/// let compressed = encoder.compress_next(data).unwrap().to_vec();
+///
+/// let mut lzxd = Lzxd::new(WindowSize::KB64);
+/// assert_eq!(lzxd.decompress_next(&compressed, data.len()).unwrap(), data);
+/// ```
+///
+/// [`Lzxd`]: struct.Lzxd.html
+/// [`WindowSize`]: enum.WindowSize.html
+/// [`Variant`]: enum.Variant.html
+pub struct LzxdEncoder {
+    /// The window size we're working with.
+    window_size: WindowSize,
+
+    /// The format variant we're encoding.
+    variant: Variant,
+
+    /// How hard to try to compress the data.
+    level: CompressionLevel,
+
+    /// The data compressed so far that may still be within reach of a match, followed by the
+    /// chunk being compressed.
+    history: Vec<u8>,
+
+    /// Looks up previous occurrences of the data in the history.
+    finder: MatchFinder,
+
+    /// Buffer holding the matches found at some position.
+    matches: Vec<Match>,
+
+    /// The path lengths of the previous main tree, against which the next one is delta-coded.
+    main_lengths: Vec<u8>,
+
+    /// The path lengths of the previous length tree, against which the next one is
+    /// delta-coded.
+    length_lengths: Vec<u8>,
+
+    /// > The three most recent real match offsets are kept in a list.
+    r: [u32; 3],
+
+    /// Has the very first chunk been written yet? Unlike the rest, it has additional data.
+    first_chunk_written: bool,
+
+    /// The translation size written in the first chunk, if E8 translation is enabled.
+    e8_translation_size: Option<i32>,
+
+    /// The offset of the next chunk in the uncompressed data, which E8 translation needs.
+    chunk_offset: usize,
+
+    /// Does the next block need to be preceded by a byte of padding, because the previous
+    /// one was an uncompressed block of an odd size?
+    pad_pending: bool,
+
+    /// Buffer holding the last compressed chunk.
+    output: Vec<u8>,
+}
+
+/// The position slot of a formatted offset, which is the last slot whose base position is
+/// not past it.
+pub(crate) fn position_slot(formatted_offset: u32) -> u16 {
+    (BASE_POSITION.partition_point(|&base| base <= formatted_offset) - 1) as u16
+}
+
+/// Writes the delta-coded path lengths of the elements in `new`, using a pretree, and updates
+/// the `prev` path lengths of the tree with them.
+///
+/// This is the inverse of `CanonicalTree::update_range_with_pretree`.
+pub(crate) fn write_tree(writer: &mut BitWriter, prev: &mut [u8], new: &[u8]) {
+    // Pretree codes, along with the bits that follow them.
+    let mut codes = Vec::new();
+    let delta = |prev: u8, new: u8| (17 + prev - new) % 17;
+
+    let mut i = 0;
+    while i < new.len() {
+        let value = new[i];
+        let run = new[i..].iter().take_while(|&&x| x == value).count();
+
+        // > Codes 17, 18, and 19 are used to represent consecutive elements that have the
+        // > same path length.
+        if value == 0 && run >= 20 {
+            let run = run.min(20 + 31);
+            codes.push((18, Some(((run - 20) as u32, 5))));
+            i += run;
+        } else if value == 0 && run >= 4 {
+            codes.push((17, Some(((run - 4) as u32, 4))));
+            i += run;
+        } else if run >= 4 {
+            let run = run.min(5);
+            codes.push((19, Some(((run - 4) as u32, 1))));
+            codes.push((delta(prev[i], value) as usize, None));
+            i += run;
+        } else {
+            codes.push((delta(prev[i], value) as usize, None));
+            i += 1;
+        }
+    }
+
+    let mut freqs = [0; 20];
+    codes.iter().for_each(|&(code, _)| freqs[code] += 1);
+    let pretree = huffman::path_lengths(&freqs, MAX_PRETREE_PATH_LENGTH);
+    let pretree_codes = huffman::codes(&pretree);
+
+    pretree
+        .iter()
+        .for_each(|&len| writer.write_bits(len as u32, 4));
+    codes.into_iter().for_each(|(code, extra)| {
+        writer.write_bits(pretree_codes[code] as u32, pretree[code]);
+        if let Some((value, bits)) = extra {
+            writer.write_bits(value, bits);
+        }
+    });
+
+    prev.copy_from_slice(new);
+}
+
+impl LzxdEncoder {
+    /// Creates a new instance of the LZXD encoder state. The [`WindowSize`] is not stored in
+    /// the compressed data stream, so it must be communicated to the decoder by other means.
+    ///
+    /// [`WindowSize`]: enum.WindowSize.html
+    pub fn new(window_size: WindowSize) -> Self {
+        Self::with_variant(window_size, Variant::Lzx)
+    }
+
+    /// Creates a new instance of the encoder state for the given [`Variant`] of the format.
+    ///
+    /// [`Variant`]: enum.Variant.html
+    pub fn with_variant(window_size: WindowSize, variant: Variant) -> Self {
+        Self::with_level(window_size, variant, CompressionLevel::default())
+    }
+
+    /// Creates a new instance of the encoder state for the given [`Variant`] of the format,
+    /// which compresses the data as hard as the [`CompressionLevel`] says.
+    ///
+    /// [`Variant`]: enum.Variant.html
+    /// [`CompressionLevel`]: struct.CompressionLevel.html
+    pub fn with_level(window_size: WindowSize, variant: Variant, level: CompressionLevel) -> Self {
+        Self {
+            window_size,
+            variant,
+            level,
+            history: Vec::new(),
+            finder: level.match_finder(),
+            matches: Vec::new(),
+            // > In the case of the very first such tree, the delta is calculated against a tree
+            // > in which all elements have a zero path length.
+            main_lengths: vec![0; 256 + 8 * window_size.position_slots()],
+            length_lengths: vec![0; 249],
+            // > The initial state of R0, R1, R2 is (1, 1, 1).
+            r: [1, 1, 1],
+            first_chunk_written: false,
+            e8_translation_size: (variant == Variant::Wim).then_some(WIM_E8_TRANSLATION_SIZE),
+            chunk_offset: 0,
+            pad_pending: false,
+            output: Vec::new(),
+        }
+    }
+
+    /// Creates a new instance of the LZXD encoder state with its window pre-loaded with
+    /// `reference` data, so that the subject data is encoded as matches into it whenever
+    /// possible, as used by LZX DELTA. The encoder uses the [`Variant::LzxDelta`] format, and
+    /// the data must be decompressed with [`Lzxd::with_reference`] and the same reference.
+    ///
+    /// The reference data, rounded up to a multiple of 32 KB, must fit in the [`WindowSize`].
+    /// As described there, the window should also have room for the subject data, or else
+    /// the start of the reference will fall out of reach of the matches.
+    ///
+    /// Note that [`Self::reset`] discards the reference data.
+    ///
+    /// [`WindowSize`]: enum.WindowSize.html
+    /// [`Variant::LzxDelta`]: enum.Variant.html#variant.LzxDelta
+    /// [`Lzxd::with_reference`]: struct.Lzxd.html#method.with_reference
+    pub fn with_reference(
+        window_size: WindowSize,
+        level: CompressionLevel,
+        reference: &[u8],
+    ) -> Result<Self, CompressError> {
+        let rounded_len = reference.len().div_ceil(MAX_CHUNK_SIZE) * MAX_CHUNK_SIZE;
+        if rounded_len > window_size.value() {
+            return Err(CompressError::WindowTooSmall);
+        }
+
+        // The decoder places the reference right before the subject data, so it's simply the
+        // start of the history. The padding in front of it is never worth matching against.
+        let mut this = Self::with_level(window_size, Variant::LzxDelta, level);
+        this.history.extend_from_slice(reference);
+        (0..reference.len()).for_each(|pos| this.skip(pos, reference.len()));
+        Ok(this)
+    }
+
+    /// Enables E8 translation with the given translation size, which is written in the first
+    /// chunk for the decoder to undo it.
+    ///
+    /// The absolute targets of the x86 CALL instructions found in the data are converted to
+    /// relative ones, which makes repeated calls to the same function look alike and compress
+    /// better. It's only worth enabling for executable code, and the translation size is
+    /// usually the size of the file. Like the decoder, the encoder leaves the last 10 bytes of
+    /// every chunk and anything past the first 1GB of data untouched.
+    ///
+    /// This must be set before compressing the first chunk, and is kept by [`Self::reset`].
+    /// The [`Variant::Wim`] format always uses a translation size of 12000000, which must not
+    /// be changed.
+    ///
+    /// [`Variant::Wim`]: enum.Variant.html#variant.Wim
+    pub fn set_e8_translation(&mut self, translation_size: Option<u32>) {
+        debug_assert!(!self.first_chunk_written);
+        debug_assert!(self.variant != Variant::Wim);
+        self.e8_translation_size = translation_size.map(|size| size as i32);
+    }
+
+    /// The longest match that can be encoded.
+    fn max_match(&self) -> usize {
+        match self.variant {
+            Variant::Lzx | Variant::Wim => MAX_MATCH,
+            // > Prefix 0b111; Number of bits to decode 15;
+            Variant::LzxDelta => MAX_MATCH + (1 << 15) - 1,
+        }
+    }
+
+    /// The largest block that the Block Size field can hold.
+    fn max_block_size(&self) -> usize {
+        match self.variant {
+            Variant::Wim if self.window_size == WindowSize::KB32 => u16::MAX as usize,
+            _ => split::MAX_BLOCK_SIZE,
+        }
+    }
+
+    /// The farthest back a match can reach.
+    fn max_offset(&self) -> usize {
+        self.window_size.value() - 3
+    }
+
+    /// How many bytes starting at `pos` in the history match those `offset` bytes before it,
+    /// up to `max_len`.
+    fn match_len(&self, pos: usize, offset: usize, max_len: usize) -> usize {
+        self.history[pos..pos + max_len]
+            .iter()
+            .zip(&self.history[pos - offset..])
+            .take_while(|(a, b)| a == b)
+            .count()
+    }
+
+    /// Finds the best match for the data at `pos` in the history, returning its length and
+    /// offset, or a length of zero if there are none worth using.
+    ///
+    /// The match finder must not have seen `pos` yet, because this gives it the position.
+    fn find_match(&mut self, pos: usize, end: usize) -> (usize, usize) {
+        let limits = Limits {
+            max_len: (end - pos).min(self.max_match()),
+            max_offset: self.max_offset().min(pos),
+        };
+
+        self.matches.clear();
+        self.finder
+            .find_matches(&self.history, pos, limits, &mut self.matches);
+        let mut best = match self.matches.last() {
+            // A short match far away takes more bits than the literals it replaces.
+            Some(m) if m.length as usize > MIN_MATCH || m.offset <= FAR_OFFSET => {
+                (m.length as usize, m.offset as usize)
+            }
+            _ => (0, 0),
+        };
+
+        // Repeated offsets are much cheaper to encode, so they're preferred even if the match
+        // is not any longer.
+        for &offset in self.r.iter() {
+            let offset = offset as usize;
+            if offset <= limits.max_offset {
+                let len = self.match_len(pos, offset, limits.max_len);
+                if len >= 2 && len >= best.0 {
+                    best = (len, offset);
+                }
+            }
+        }
+
+        best
+    }
+
+    /// Lets the match finder know about the data at `pos` in the history without looking for
+    /// matches.
+    fn skip(&mut self, pos: usize, end: usize) {
+        let limits = Limits {
+            max_len: (end - pos).min(self.max_match()),
+            max_offset: self.max_offset().min(pos),
+        };
+        self.finder.skip(&self.history, pos, limits);
+    }
+
+    /// Turns a match into a token, updating the repeated offsets as the decoder will.
+    fn match_token(&mut self, offset: usize, length: usize) -> Token {
+        let offset = offset as u32;
+        let (position_slot, footer) = if offset == self.r[0] {
+            (0, 0)
+        } else if offset == self.r[1] {
+            self.r.swap(0, 1);
+            (1, 0)
+        } else if offset == self.r[2] {
+            self.r.swap(0, 2);
+            (2, 0)
+        } else {
+            let formatted_offset = offset + 2;
+            let position_slot = position_slot(formatted_offset);
+            self.r[2] = self.r[1];
+            self.r[1] = self.r[0];
+            self.r[0] = offset;
+            (
+                position_slot,
+                formatted_offset - BASE_POSITION[position_slot as usize],
+            )
+        };
+
+        Token::Match {
+            length: length as u32,
+            position_slot,
+            footer,
+        }
+    }
+
+    /// Splits the data between `start` and `end` in the history into literals and matches.
+    ///
+    /// Matches are taken greedily, unless the level asks to check if a longer one starts at
+    /// the next byte, in which case the current byte becomes a literal (lazy matching).
+    fn parse(&mut self, start: usize, end: usize) -> Vec<Token> {
+        let params = self.level.params();
+        let mut tokens = Vec::new();
+        let mut pos = start;
+        let mut next_match = None;
+        while pos < end {
+            let (length, offset) = match next_match.take() {
+                Some(found) => found,
+                None => self.find_match(pos, end),
+            };
+
+            if length < 2 {
+                tokens.push(Token::Literal(self.history[pos]));
+                pos += 1;
+                continue;
+            }
+
+            let mut skip_from = pos + 1;
+            if params.parser == Parser::Lazy && length < params.nice_len && pos + 1 < end {
+                let found = self.find_match(pos + 1, end);
+                if found.0 > length {
+                    tokens.push(Token::Literal(self.history[pos]));
+                    pos += 1;
+                    next_match = Some(found);
+                    continue;
+                }
+                skip_from += 1;
+            }
+
+            tokens.push(self.match_token(offset, length));
+            (skip_from..pos + length).for_each(|p| self.skip(p, end));
+            pos += length;
+        }
+        tokens
+    }
+
+    /// Splits the data between `start` and the end of the history into literals and matches
+    /// with the near-optimal parser.
+    fn parse_optimal(&mut self, start: usize, passes: usize) -> Vec<Token> {
+        let end = self.history.len();
+        let nice_len = self.level.params().nice_len;
+
+        // Every pass considers the same matches, so they're only looked up once. Positions
+        // covered by a match long enough to take are skipped, like the other parsers do.
+        let mut table = MatchTable::new();
+        let mut pos = start;
+        while pos < end {
+            self.matches.clear();
+            let limits = Limits {
+                max_len: (end - pos).min(self.max_match()),
+                max_offset: self.max_offset().min(pos),
+            };
+            self.finder
+                .find_matches(&self.history, pos, limits, &mut self.matches);
+            table.push(&self.matches);
+            pos += 1;
+
+            if let Some(m) = self
+                .matches
+                .last()
+                .filter(|m| m.length as usize >= nice_len)
+            {
+                let skip_end = pos - 1 + m.length as usize;
+                while pos < skip_end {
+                    self.skip(pos, end);
+                    table.push(&[]);
+                    pos += 1;
+                }
+            }
+        }
+
+        let input = optimal::Input {
+            history: &self.history,
+            start,
+            max_match: self.max_match(),
+            max_offset: self.max_offset(),
+            nice_len,
+            delta: self.variant == Variant::LzxDelta,
+        };
+        let main_elements = self.main_lengths.len();
+        let length_elements = self.length_lengths.len();
+        let mut costs = Costs::initial(main_elements, length_elements);
+        let mut parsed = optimal::parse(&input, &table, self.r, &costs);
+        for _ in 1..passes {
+            costs = Costs::from_tokens(&parsed.0, main_elements, length_elements);
+            parsed = optimal::parse(&input, &table, self.r, &costs);
+        }
+
+        let (tokens, r) = parsed;
+        self.r = r;
+        tokens
+    }
+
+    /// Writes the header of a block of `size` bytes, after which the repeated offsets are
+    /// `r`, along with its trees if it's compressed.
+    fn write_block_header(
+        &mut self,
+        writer: &mut BitWriter,
+        cost: &BlockCost,
+        size: usize,
+        r: [u32; 3],
+    ) {
+        // > Each block of compressed data begins with a 3-bit Block Type field.
+        match cost.block_type {
+            BlockType::Verbatim => {
+                writer.write_bits(0b001, 3);
+                self.write_block_size(writer, size);
+            }
+            BlockType::AlignedOffset => {
+                writer.write_bits(0b010, 3);
+                self.write_block_size(writer, size);
+                // > An aligned offset block is identical to the verbatim block except for the
+                // > presence of the aligned offset tree preceding the other trees.
+                //
+                // Its eight path lengths take 3 bits each, and are not delta-coded.
+                cost.trees
+                    .aligned
+                    .as_ref()
+                    .unwrap()
+                    .iter()
+                    .for_each(|&len| writer.write_bits(len as u32, 3));
+            }
+            BlockType::Uncompressed => {
+                writer.write_bits(0b011, 3);
+                self.write_block_size(writer, size);
+                // The repeated offsets start at the next 16-bit boundary, which is a whole 16
+                // bits away if the header happens to end on one. The decoder takes them as
+                // they are, so they must be the ones the tokens after the block expect.
+                writer.align();
+                r.iter().for_each(|&r| writer.write_u32_le(r));
+                return;
+            }
+        }
+
+        let trees = &cost.trees;
+        write_tree(writer, &mut self.main_lengths[..256], &trees.main[..256]);
+        write_tree(writer, &mut self.main_lengths[256..], &trees.main[256..]);
+        write_tree(writer, &mut self.length_lengths, &trees.length);
+    }
+
+    /// Writes the Block Size field, which WIM streams replace with a single bit for blocks of
+    /// the default size.
+    fn write_block_size(&self, writer: &mut BitWriter, size: usize) {
+        match self.variant {
+            Variant::Wim if size as u32 == WIM_DEFAULT_BLOCK_SIZE => writer.write_bit(true),
+            Variant::Wim if self.window_size == WindowSize::KB32 => {
+                writer.write_bit(false);
+                writer.write_bits(size as u32, 16);
+            }
+            Variant::Wim => {
+                writer.write_bit(false);
+                writer.write_u24_be(size as u32);
+            }
+            Variant::Lzx | Variant::LzxDelta => writer.write_u24_be(size as u32),
+        }
+    }
+
+    /// Writes a token of a compressed block with the given codes.
+    fn write_token(writer: &mut BitWriter, variant: Variant, codes: &BlockCodes, token: Token) {
+        let trees = codes.trees;
+        let (main_element, length_element) = token.elements();
+        writer.write_bits(codes.main[main_element] as u32, trees.main[main_element]);
+        if let Some(length_element) = length_element {
+            writer.write_bits(
+                codes.length[length_element] as u32,
+                trees.length[length_element],
+            );
+        }
+
+        if let Token::Match {
+            length,
+            position_slot,
+            footer,
+        } = token
+        {
+            let footer_bits = FOOTER_BITS[position_slot as usize];
+            match (&codes.aligned, token.aligned_element()) {
+                _ if position_slot < 3 => {}
+                (Some(aligned_codes), Some(aligned_element)) => {
+                    let aligned_lengths = trees.aligned.as_ref().unwrap();
+                    writer.write_bits(footer >> 3, footer_bits - 3);
+                    writer.write_bits(
+                        aligned_codes[aligned_element] as u32,
+                        aligned_lengths[aligned_element],
+                    );
+                }
+                _ => writer.write_bits(footer, footer_bits),
+            }
+            if variant == Variant::LzxDelta && length >= MAX_MATCH as u32 {
+                Self::write_extra_length(writer, length - MAX_MATCH as u32);
+            }
+        }
+    }
+
+    /// Writes the Extra Length field of LZX DELTA for matches of 257 bytes or longer.
+    fn write_extra_length(writer: &mut BitWriter, extra_len: u32) {
+        if extra_len < 256 {
+            // > Prefix 0b0; Number of bits to decode 8;
+            writer.write_bits(0b0, 1);
+            writer.write_bits(extra_len, 8);
+        } else if extra_len < 256 + 1024 {
+            // > Prefix 0b10; Number of bits to decode 10;
+            writer.write_bits(0b10, 2);
+            writer.write_bits(extra_len - 256, 10);
+        } else if extra_len < 256 + 1024 + 4096 {
+            // > Prefix 0b110; Number of bits to decode 12;
+            writer.write_bits(0b110, 3);
+            writer.write_bits(extra_len - 1024 - 256, 12);
+        } else {
+            // > Prefix 0b111; Number of bits to decode 15;
+            writer.write_bits(0b111, 3);
+            writer.write_bits(extra_len, 15);
+        }
+    }
+
+    /// Performs E8 translation on the next chunk, if it's enabled, before compressing it.
+    fn preprocess<'a>(&mut self, chunk: &'a [u8]) -> Cow<'a, [u8]> {
+        let chunk_offset = self.chunk_offset;
+        self.chunk_offset += chunk.len();
+
+        match self.e8_translation_size {
+            Some(size) if Lzxd::needs_postprocess(chunk_offset, chunk.len()) => {
+                let mut chunk = chunk.to_vec();
+                translate(size, chunk_offset, &mut chunk);
+                Cow::Owned(chunk)
+            }
+            _ => Cow::Borrowed(chunk),
+        }
+    }
+
+    /// Compresses the chunks of data, letting blocks span them, and returns the compressed
+    /// data of each one.
+    ///
+    /// Every chunk must be [`MAX_CHUNK_SIZE`] bytes long, except for the last one.
+    fn compress_segment(&mut self, chunks: &[&[u8]]) -> Vec<Vec<u8>> {
+        let chunks = chunks
+            .iter()
+            .map(|chunk| self.preprocess(chunk))
+            .collect::<Vec<_>>();
+
+        let initial_r = self.r;
+        let mut tokens = Vec::new();
+        for chunk in &chunks {
+            // Only keep as much history as matches can reach. It's let grow to twice that
+            // before discarding it, so that it's not shifted with every chunk.
+            let window_size = self.window_size.value();
+            if self.history.len() >= 2 * window_size {
+                let excess = self.history.len() - window_size;
+                self.history.drain(..excess);
+                self.finder.slide(excess);
+            }
+
+            let start = self.history.len();
+            self.history.extend_from_slice(chunk);
+            if !chunk.is_empty() {
+                tokens.extend(match self.level.params().parser {
+                    Parser::Optimal { passes } => self.parse_optimal(start, passes),
+                    Parser::Greedy | Parser::Lazy => self.parse(start, self.history.len()),
+                });
+            }
+        }
+
+        let mut writers = chunks.iter().map(|_| BitWriter::new()).collect::<Vec<_>>();
+        if !self.first_chunk_written && self.variant != Variant::Wim {
+            // > The first bit in the first chunk in the LZXD bitstream (following the 2-byte,
+            // > chunk-size prefix described in section 2.2.1) indicates the presence or absence
+            // > of two 16-bit fields immediately following the single bit.
+            match self.e8_translation_size {
+                Some(size) => {
+                    writers[0].write_bit(true);
+                    writers[0].write_bits(size as u32, 32);
+                }
+                None => writers[0].write_bit(false),
+            }
+        }
+        self.first_chunk_written = true;
+
+        let delta = self.variant == Variant::LzxDelta;
+        let (main_elements, length_elements) = (self.main_lengths.len(), self.length_lengths.len());
+        let blocks = split::split(
+            &tokens,
+            &self.main_lengths,
+            &self.length_lengths,
+            delta,
+            self.max_block_size(),
+        );
+
+        // Where the next block starts in the chunks, and the repeated offsets before it.
+        let mut pos = 0;
+        let mut r = initial_r;
+        for block in blocks {
+            let tokens = &tokens[block];
+            let stats = BlockStats::of(tokens, main_elements, length_elements, delta);
+            let cost = stats.cost(&self.main_lengths, &self.length_lengths);
+            let size = stats.size();
+            tokens
+                .iter()
+                .for_each(|token| token.update_repeated_offsets(&mut r));
+
+            // Uncompressed blocks of an odd size are followed by a byte of padding, which the
+            // decoder skips when it reads the next block, even if it's in the next chunk.
+            let writer = &mut writers[pos / MAX_CHUNK_SIZE];
+            if mem::take(&mut self.pad_pending) {
+                writer.write_byte(0);
+            }
+            self.write_block_header(writer, &cost, size, r);
+
+            if cost.block_type == BlockType::Uncompressed {
+                let end = pos + size;
+                while pos < end {
+                    let (chunk, offset) = (pos / MAX_CHUNK_SIZE, pos % MAX_CHUNK_SIZE);
+                    let len = (end - pos).min(chunks[chunk].len() - offset);
+                    writers[chunk].write_raw(&chunks[chunk][offset..offset + len]);
+                    pos += len;
+                }
+                self.pad_pending = size % 2 != 0;
+            } else {
+                let codes = BlockCodes::new(&cost);
+                for &token in tokens {
+                    let writer = &mut writers[pos / MAX_CHUNK_SIZE];
+                    Self::write_token(writer, self.variant, &codes, token);
+                    pos += token.len();
+                }
+            }
+        }
+        debug_assert_eq!(r, self.r);
+
+        writers.into_iter().map(BitWriter::finish).collect()
+    }
+
+    /// Compresses the next `chunk` of data into the LZXD data stream.
+    ///
+    /// Every chunk must be [`MAX_CHUNK_SIZE`] bytes long, except for the last one, which may
+    /// be shorter. The decoder must be told the length of each chunk once decompressed.
+    ///
+    /// Blocks never span more than one chunk when compressing them one at a time. Use
+    /// [`Self::compress_chunks`] to let them grow as large as the data allows.
+    ///
+    /// [`MAX_CHUNK_SIZE`]: constant.MAX_CHUNK_SIZE.html
+    pub fn compress_next(&mut self, chunk: &[u8]) -> Result<&[u8], CompressError> {
+        if chunk.len() > MAX_CHUNK_SIZE {
+            return Err(CompressError::ChunkTooLong);
+        }
+
+        self.output = self.compress_segment(&[chunk]).pop().unwrap();
+        Ok(&self.output)
+    }
+
+    /// Compresses the `data` into the LZXD data stream as consecutive chunks of
+    /// [`MAX_CHUNK_SIZE`] bytes (the last one may be shorter), returning the compressed data
+    /// of each one.
+    ///
+    /// Unlike [`Self::compress_next`], this may produce blocks that span several chunks, up
+    /// to the largest block size of the format. As with it, if the last chunk is shorter, the
+    /// data stream ends there.
+    ///
+    /// ```
+    /// use ::lzxd::{Lzxd, LzxdEncoder, WindowSize, MAX_CHUNK_SIZE};
+    ///
+    /// let data = b"Hello, world! ".repeat(10000);
+    ///
+    /// let mut encoder = LzxdEncoder::new(WindowSize::KB64);
+    /// let compressed = encoder.compress_chunks(&data);
+    ///
+    /// let mut lzxd = Lzxd::new(WindowSize::KB64);
+    /// for (chunk, data) in compressed.iter().zip(data.chunks(MAX_CHUNK_SIZE)) {
+    ///     assert_eq!(lzxd.decompress_next(chunk, data.len()).unwrap(), data);
+    /// }
+    /// ```
+    ///
+    /// [`MAX_CHUNK_SIZE`]: constant.MAX_CHUNK_SIZE.html
+    pub fn compress_chunks(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        let chunks = data.chunks(MAX_CHUNK_SIZE).collect::<Vec<_>>();
+        if chunks.is_empty() {
+            return Vec::new();
+        }
+        self.compress_segment(&chunks)
+    }
+
+    /// Resets the encoder state.
+    ///
+    /// This is equivalent to calling [`Self::with_level`] with the same [`WindowSize`],
+    /// [`Variant`] and [`CompressionLevel`].
+    ///
+    /// [`WindowSize`]: enum.WindowSize.html
+    /// [`Variant`]: enum.Variant.html
+    /// [`CompressionLevel`]: struct.CompressionLevel.html
+    pub fn reset(&mut self) {
+        let e8_translation_size = self.e8_translation_size;
+        *self = Self::with_level(self.window_size, self.variant, self.level);
+        self.e8_translation_size = e8_translation_size;
+    }
+
+    /// Resets the encoder state like [`Self::reset`] does, but with the next chunk at the
+    /// given `position` of the uncompressed data instead of at its start, for E8 translation.
+    ///
+    /// The decoder must be reset at the same point with [`Lzxd::reset_at`].
+    ///
+    /// [`Lzxd::reset_at`]: struct.Lzxd.html#method.reset_at
+    pub fn reset_at(&mut self, position: usize) {
+        self.reset();
+        self.chunk_offset = position;
+    }
+}
+
+/// Converts the absolute targets of the CALL instructions in the `data` to relative ones,
+/// exactly undoing what [`Lzxd::postprocess`] does to the chunk at `chunk_offset`.
+fn translate(translation_size: i32, chunk_offset: usize, data: &mut [u8]) {
+    let mut processed = 0usize;
+    while let Some(pos) = data[processed..]
+        .iter()
+        .position(|&e| e == 0xE8)
+        .map(|pos| processed + pos)
+    {
+        // The decoder leaves the last 10 bytes of the chunk alone, so they're left as-is.
+        if data.len() - pos <= 10 {
+            break;
+        }
+
+        let current_pointer = (chunk_offset + pos) as i32;
+        let rel_val =
+            i32::from_le_bytes([data[pos + 1], data[pos + 2], data[pos + 3], data[pos + 4]]);
+        if rel_val >= -current_pointer && rel_val < translation_size {
+            // The decoder subtracts the current pointer from positive values and adds the
+            // translation size to negative ones, so whichever gives a value it would map back
+            // is used.
+            let abs_val = if rel_val < translation_size - current_pointer {
+                rel_val + current_pointer
+            } else {
+                rel_val - translation_size
+            };
+            data[pos + 1..pos + 5].copy_from_slice(&abs_val.to_le_bytes());
+        }
+
+        // The 4 bytes after a CALL are never the start of another one, even if untouched.
+        processed = pos + 5;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decompress_all, BlockInfo, Framing, Visitor};
+
+    /// Deterministic pseudo-random bytes, which hardly compress.
+    fn noise(len: usize, mut seed: u32) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect()
+    }
+
+    /// Text-like data with plenty of repetitions at varying distances.
+    fn text(len: usize) -> Vec<u8> {
+        let words = [
+            &b"lorem "[..],
+            b"ipsum ",
+            b"dolor ",
+            b"sit ",
+            b"amet, ",
+            b"consectetur ",
+            b"adipiscing ",
+            b"elit. ",
+        ];
+        let mut seed = 7u32;
+        let mut data = Vec::with_capacity(len + 16);
+        while data.len() < len {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            data.extend(words[(seed >> 16) as usize % words.len()]);
+        }
+        data.truncate(len);
+        data
+    }
+
+    /// Code-like data with CALL instructions to a few functions, whose targets are relative
+    /// to the instruction like in x86 code, along with some that aren't calls at all.
+    fn calls(len: usize) -> Vec<u8> {
+        let functions = [0x40, 0x1234, 0x8000, 0x1_0000, 0x1_8000];
+        let mut seed = 3u32;
+        let mut data = Vec::with_capacity(len + 16);
+        while data.len() < len {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let pos = data.len() as i32;
+            match (seed >> 16) % 4 {
+                0 => data.extend(b"\x55\x8B\xEC"),
+                1 => data.extend(b"\xE8\xFF\xFF\xFF\xFF"),
+                _ => {
+                    let target = functions[(seed >> 18) as usize % functions.len()];
+                    data.push(0xE8);
+                    data.extend((target - pos).to_le_bytes());
+                }
+            }
+        }
+        data.truncate(len);
+        data
+    }
+
+    fn round_trip(data: &[u8], window_size: WindowSize, variant: Variant) -> usize {
+        round_trip_level(data, window_size, variant, CompressionLevel::default())
+    }
+
+    fn round_trip_level(
+        data: &[u8],
+        window_size: WindowSize,
+        variant: Variant,
+        level: CompressionLevel,
+    ) -> usize {
+        let mut encoder = LzxdEncoder::with_level(window_size, variant, level);
+        let mut lzxd = Lzxd::with_variant(window_size, variant);
+        let mut compressed_len = 0;
+        for chunk in data.chunks(MAX_CHUNK_SIZE) {
+            let compressed = encoder.compress_next(chunk).unwrap();
+            compressed_len += compressed.len();
+            assert_eq!(
+                lzxd.decompress_next(compressed, chunk.len()).unwrap(),
+                chunk
+            );
+        }
+        compressed_len
+    }
+
+    /// Decompresses the chunks, checking that they match the data, and returns the type and
+    /// size of every block in them.
+    fn decompress_blocks(
+        compressed: &[Vec<u8>],
+        data: &[u8],
+        window_size: WindowSize,
+    ) -> Vec<(BlockType, u32)> {
+        struct Blocks(Vec<(BlockType, u32)>);
+        impl Visitor for Blocks {
+            fn on_block_start(&mut self, block: &BlockInfo<'_>) {
+                self.0.push((block.block_type(), block.size()));
+            }
+        }
+
+        let mut lzxd = Lzxd::new(window_size);
+        let mut blocks = Blocks(Vec::new());
+        assert_eq!(compressed.len(), data.chunks(MAX_CHUNK_SIZE).len());
+        for (compressed, chunk) in compressed.iter().zip(data.chunks(MAX_CHUNK_SIZE)) {
+            let decompressed = lzxd
+                .decompress_next_with_visitor(compressed, chunk.len(), &mut blocks)
+                .unwrap();
+            assert_eq!(decompressed, chunk);
+        }
+        blocks.0
+    }
+
+    /// The types of the blocks the data is compressed into, one chunk at a time.
+    fn block_types(data: &[u8], window_size: WindowSize) -> Vec<BlockType> {
+        let mut encoder = LzxdEncoder::new(window_size);
+        let compressed = data
+            .chunks(MAX_CHUNK_SIZE)
+            .map(|chunk| encoder.compress_next(chunk).unwrap().to_vec())
+            .collect::<Vec<_>>();
+        decompress_blocks(&compressed, data, window_size)
+            .into_iter()
+            .map(|(block_type, _)| block_type)
+            .collect()
+    }
+
+    #[test]
+    fn round_trip_small() {
+        round_trip(b"a", WindowSize::KB32, Variant::Lzx);
+        round_trip(b"ab", WindowSize::KB32, Variant::Lzx);
+        round_trip(b"aaaaaaaaaaaaaaaa", WindowSize::KB32, Variant::Lzx);
+        round_trip(b"abcabcabcabcabc", WindowSize::KB32, Variant::Lzx);
+    }
+
+    #[test]
+    fn round_trip_text() {
+        let data = text(5 * MAX_CHUNK_SIZE + 1234);
+        let compressed_len = round_trip(&data, WindowSize::KB64, Variant::Lzx);
+        assert!(compressed_len < data.len() / 3);
+    }
+
+    #[test]
+    fn round_trip_noise() {
+        let data = noise(3 * MAX_CHUNK_SIZE, 1);
+        let compressed_len = round_trip(&data, WindowSize::KB32, Variant::Lzx);
+        // Stored as uncompressed blocks, with only their headers on top.
+        assert!(compressed_len <= data.len() + 3 * 16);
+        assert_eq!(
+            block_types(&data, WindowSize::KB32),
+            [BlockType::Uncompressed; 3]
+        );
+    }
+
+    #[test]
+    fn aligned_offset_blocks() {
+        // Records of 16 bytes picked from far behind, so matches are at offsets that are
+        // multiples of 16, which the aligned offset tree encodes in a single bit.
+        let records = noise(256 * 16, 8);
+        let mut seed = 9u32;
+        let mut data = records.clone();
+        while data.len() < 2 * MAX_CHUNK_SIZE {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let record = (seed >> 16) as usize % 256;
+            data.extend_from_slice(&records[record * 16..][..16]);
+            data.push((seed >> 8) as u8);
+            data.extend(noise(15, seed));
+        }
+
+        round_trip(&data, WindowSize::KB64, Variant::Lzx);
+        assert!(block_types(&data, WindowSize::KB64).contains(&BlockType::AlignedOffset));
+    }
+
+    #[test]
+    fn odd_uncompressed_block() {
+        let data = noise(MAX_CHUNK_SIZE - 1, 10);
+        round_trip(&data, WindowSize::KB32, Variant::Lzx);
+        assert_eq!(
+            block_types(&data, WindowSize::KB32),
+            [BlockType::Uncompressed]
+        );
+    }
+
+    #[test]
+    fn blocks_span_chunks() {
+        let data = text(4 * MAX_CHUNK_SIZE + 1000);
+        let mut encoder = LzxdEncoder::new(WindowSize::KB64);
+        let compressed = encoder.compress_chunks(&data);
+        let blocks = decompress_blocks(&compressed, &data, WindowSize::KB64);
+        assert!(blocks
+            .iter()
+            .any(|&(_, size)| size as usize > MAX_CHUNK_SIZE));
+
+        // Blocks are not limited to a chunk, so there are fewer trees to write.
+        let chunked_len = round_trip(&data, WindowSize::KB64, Variant::Lzx);
+        assert!(compressed.iter().map(Vec::len).sum::<usize>() < chunked_len);
+    }
+
+    #[test]
+    fn blocks_split_on_mixed_data() {
+        let mut data = text(MAX_CHUNK_SIZE);
+        data.extend(noise(MAX_CHUNK_SIZE, 11));
+        data.extend(text(MAX_CHUNK_SIZE / 2));
+        data.extend((0..MAX_CHUNK_SIZE).map(|i| (i / 64) as u8));
+
+        let mut encoder = LzxdEncoder::new(WindowSize::KB64);
+        let compressed = encoder.compress_chunks(&data);
+        let blocks = decompress_blocks(&compressed, &data, WindowSize::KB64);
+        let block_ends = blocks
+            .iter()
+            .scan(0, |end, &(_, size)| {
+                *end += size as usize;
+                Some(*end)
+            })
+            .collect::<Vec<_>>();
+
+        // Every change in the data starts a new block soon after.
+        for change in [MAX_CHUNK_SIZE, 2 * MAX_CHUNK_SIZE, 5 * MAX_CHUNK_SIZE / 2] {
+            assert!(block_ends
+                .iter()
+                .any(|&end| end >= change && end < change + 4096));
+        }
+    }
+
+    #[test]
+    fn odd_uncompressed_block_then_more() {
+        // The few bytes repeated at the end of the noise make its first block odd-sized.
+        let mut data = noise(4091, 12);
+        data.extend(b"xyzxyz");
+        data.extend(text(2 * MAX_CHUNK_SIZE));
+
+        let mut encoder = LzxdEncoder::new(WindowSize::KB64);
+        let compressed = encoder.compress_chunks(&data);
+        let blocks = decompress_blocks(&compressed, &data, WindowSize::KB64);
+        assert_eq!(blocks[0], (BlockType::Uncompressed, 4097));
+        assert!(blocks.len() > 1);
+    }
+
+    #[test]
+    fn round_trip_long_matches() {
+        let mut data = noise(1000, 2);
+        data.extend(vec![b'x'; 2 * MAX_CHUNK_SIZE]);
+        data.extend(noise(1000, 3));
+        data.extend_from_within(..);
+
+        let lzx_len = round_trip(&data, WindowSize::MB2, Variant::Lzx);
+        let delta_len = round_trip(&data, WindowSize::MB2, Variant::LzxDelta);
+        assert!(delta_len < lzx_len);
+    }
+
+    #[test]
+    fn round_trip_far_matches() {
+        // The same data repeated just within reach of the window.
+        let mut data = noise(MAX_CHUNK_SIZE, 4);
+        data.extend(noise(MAX_CHUNK_SIZE - 10, 5));
+        data.extend_from_within(..MAX_CHUNK_SIZE);
+
+        let compressed_len = round_trip(&data, WindowSize::KB64, Variant::Lzx);
+        assert!(compressed_len < 2 * MAX_CHUNK_SIZE + MAX_CHUNK_SIZE / 8);
+    }
+
+    #[test]
+    fn round_trip_levels() {
+        let mut data = text(MAX_CHUNK_SIZE + 1000);
+        data.extend(noise(MAX_CHUNK_SIZE / 4, 6));
+        data.extend_from_within(..MAX_CHUNK_SIZE / 2);
+
+        let lengths = (1..=9)
+            .map(|level| {
+                let level = CompressionLevel::new(level).unwrap();
+                round_trip_level(&data, WindowSize::KB64, Variant::Lzx, level)
+            })
+            .collect::<Vec<_>>();
+        assert!(lengths[8] <= lengths[0]);
+    }
+
+    #[test]
+    fn optimal_parse_beats_lazy() {
+        let data = text(2 * MAX_CHUNK_SIZE);
+        let lazy = round_trip_level(&data, WindowSize::KB64, Variant::Lzx, CompressionLevel(7));
+        let optimal = round_trip_level(
+            &data,
+            WindowSize::KB64,
+            Variant::Lzx,
+            CompressionLevel::BEST,
+        );
+        assert!(optimal < lazy);
+    }
+
+    #[test]
+    fn optimal_parse_long_matches() {
+        let mut data = noise(1000, 2);
+        data.extend(vec![b'x'; MAX_CHUNK_SIZE]);
+        data.extend_from_within(..);
+        round_trip_level(
+            &data,
+            WindowSize::MB2,
+            Variant::LzxDelta,
+            CompressionLevel::BEST,
+        );
+    }
+
+    #[test]
+    fn compression_levels() {
+        assert_eq!(CompressionLevel::new(0), None);
+        assert_eq!(CompressionLevel::new(10), None);
+        assert_eq!(CompressionLevel::new(1), Some(CompressionLevel::FASTEST));
+        assert_eq!(CompressionLevel::new(9), Some(CompressionLevel::BEST));
+        assert_eq!(CompressionLevel::default().get(), 6);
+    }
+
+    #[test]
+    fn round_trip_empty_chunks() {
+        let mut encoder = LzxdEncoder::new(WindowSize::KB32);
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        let compressed = encoder.compress_next(&[]).unwrap();
+        assert_eq!(lzxd.decompress_next(compressed, 0).unwrap(), b"");
+        let compressed = encoder.compress_next(b"abc").unwrap();
+        assert_eq!(lzxd.decompress_next(compressed, 3).unwrap(), b"abc");
+        assert!(encoder.compress_next(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn compress_too_long() {
+        let mut encoder = LzxdEncoder::new(WindowSize::KB32);
+        assert_eq!(
+            encoder.compress_next(&[0; MAX_CHUNK_SIZE + 1]).err(),
+            Some(CompressError::ChunkTooLong)
+        );
+    }
+
+    #[test]
+    fn reset() {
+        let data = text(1000);
+        let mut encoder = LzxdEncoder::new(WindowSize::KB32);
+        let first = encoder.compress_next(&data).unwrap().to_vec();
+        encoder.reset();
+        assert_eq!(encoder.compress_next(&data).unwrap(), first);
+    }
+
+    #[test]
+    fn tree_deltas() {
+        let prev = [0, 3, 3, 3, 3, 3, 0, 0, 0, 0, 0, 2, 16];
+        let new = [1, 3, 3, 3, 3, 3, 0, 0, 0, 0, 0, 0, 4];
+        let mut writer = BitWriter::new();
+        let mut updated = prev;
+        write_tree(&mut writer, &mut updated, &new);
+        assert_eq!(updated, new);
+
+        let bytes = writer.finish();
+        let mut tree = crate::CanonicalTree::from_path_lengths(prev.to_vec());
+        tree.update_range_with_pretree(&mut crate::Bitstream::new(&bytes), 0..prev.len())
+            .unwrap();
+        assert_eq!(tree.path_lengths(), new);
+    }
+
+    #[test]
+    fn round_trip_e8() {
+        let data = calls(3 * MAX_CHUNK_SIZE + 1000);
+        let mut encoder = LzxdEncoder::new(WindowSize::KB64);
+        encoder.set_e8_translation(Some(data.len() as u32));
+        let compressed = encoder.compress_chunks(&data);
+
+        let mut lzxd = Lzxd::new(WindowSize::KB64);
+        for (chunk, data) in compressed.iter().zip(data.chunks(MAX_CHUNK_SIZE)) {
+            assert_eq!(lzxd.decompress_next(chunk, data.len()).unwrap(), data);
+        }
+
+        // Matches into the previous chunks must see them as they were before being translated
+        // back, which the decoder must also do when decompressing them all at once.
+        let mut framed = Vec::new();
+        for chunk in &compressed {
+            framed.extend((chunk.len() as u16).to_le_bytes());
+            framed.extend(chunk);
+        }
+        let decompressed = decompress_all(
+            &framed,
+            Framing::MsPatch,
+            WindowSize::KB64,
+            Variant::Lzx,
+            data.len(),
+        );
+        assert_eq!(decompressed.unwrap(), data);
+
+        // The calls look alike once translated, so they compress better.
+        let plain = LzxdEncoder::new(WindowSize::KB64).compress_chunks(&data);
+        assert!(compressed.concat().len() < plain.concat().len());
+    }
+
+    #[test]
+    fn round_trip_e8_chunks() {
+        let data = calls(2 * MAX_CHUNK_SIZE + 15);
+        let mut encoder = LzxdEncoder::new(WindowSize::KB32);
+        encoder.set_e8_translation(Some(12_000_000));
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        // The last chunk is too short for E8 translation.
+        for data in data.chunks(MAX_CHUNK_SIZE).chain([&data[..10]]) {
+            let compressed = encoder.compress_next(data).unwrap();
+            assert_eq!(lzxd.decompress_next(compressed, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn e8_translation_is_undone() {
+        let mut data = noise(4000, 4);
+        data.iter_mut().step_by(7).for_each(|b| *b = 0xE8);
+        // Calls to the start of the data and to both ends of the translated range, where a CALL
+        // would be found after the previous ones.
+        data[105..110].copy_from_slice(&[0xE8, 0x97, 0xFF, 0xFF, 0xFF]);
+        data[203..208].copy_from_slice(&[0xE8, 0x00, 0x00, 0x00, 0x00]);
+        data[301..306].copy_from_slice(&[0xE8, 0xFF, 0xFF, 0x00, 0x00]);
+
+        for chunk_offset in [0, 100, MAX_CHUNK_SIZE, 0x3FFF_8000] {
+            for translation_size in [1, 0x1_0000, 12_000_000, i32::MAX] {
+                let mut translated = data.clone();
+                translate(translation_size, chunk_offset, &mut translated);
+                assert_ne!(translated, data);
+                assert_eq!(
+                    Lzxd::postprocess(translation_size, chunk_offset, &mut translated, &mut ()),
+                    data
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn e8_translation_limits() {
+        let mut data = vec![0; 30];
+        data[10..15].copy_from_slice(&[0xE8, 0x00, 0x01, 0x00, 0x00]);
+        data[20..25].copy_from_slice(&[0xE8, 0x00, 0x01, 0x00, 0x00]);
+
+        // The last 10 bytes of a chunk are left alone.
+        let mut translated = data.clone();
+        translate(0x1_0000, 0, &mut translated);
+        assert_eq!(translated[11..15], (0x100i32 + 10).to_le_bytes());
+        assert_eq!(translated[20..], data[20..]);
+
+        // And so is anything past the first 1GB.
+        let mut encoder = LzxdEncoder::new(WindowSize::KB32);
+        encoder.set_e8_translation(Some(0x1_0000));
+        assert!(matches!(encoder.preprocess(&data), Cow::Owned(_)));
+        encoder.chunk_offset = 0x4000_0000;
+        assert!(matches!(encoder.preprocess(&data), Cow::Borrowed(_)));
+    }
+
+    /// Compresses the `subject` against the `reference` and checks that it decompresses back,
+    /// returning the compressed size.
+    fn round_trip_reference(reference: &[u8], subject: &[u8], level: CompressionLevel) -> usize {
+        let window_size = WindowSize::KB256;
+        let mut encoder = LzxdEncoder::with_reference(window_size, level, reference).unwrap();
+        let compressed = encoder.compress_chunks(subject);
+
+        let mut lzxd = Lzxd::with_reference(window_size, reference).unwrap();
+        for (chunk, data) in compressed.iter().zip(subject.chunks(MAX_CHUNK_SIZE)) {
+            assert_eq!(lzxd.decompress_next(chunk, data.len()).unwrap(), data);
+        }
+        compressed.iter().map(Vec::len).sum()
+    }
+
+    #[test]
+    fn round_trip_reference_matches() {
+        let reference = noise(50_000, 5);
+        let mut subject = reference[10_000..30_000].to_vec();
+        subject.extend(b"a small change");
+        subject.extend(&reference[..20_000]);
+        subject.extend(text(5000));
+
+        for level in [CompressionLevel::FASTEST, CompressionLevel::BEST] {
+            let with_reference = round_trip_reference(&reference, &subject, level);
+            let without = LzxdEncoder::with_level(WindowSize::KB256, Variant::LzxDelta, level)
+                .compress_chunks(&subject)
+                .concat()
+                .len();
+            // The noise taken from the reference is only compressible by matching it there.
+            assert!(with_reference < without / 4);
+        }
+    }
+
+    #[test]
+    fn round_trip_reference_sizes() {
+        // References that are not a multiple of the chunk size, and that fill the window.
+        round_trip_reference(b"abc", b"xabcabcx", CompressionLevel::DEFAULT);
+        let reference = text(WindowSize::KB256.value());
+        round_trip_reference(
+            &reference,
+            &reference[1000..90_000],
+            CompressionLevel::DEFAULT,
+        );
+    }
+
+    #[test]
+    fn round_trip_reference_e8() {
+        let reference = calls(40_000);
+        let mut subject = reference[5000..25_000].to_vec();
+        subject.extend(calls(30_000));
+
+        let window_size = WindowSize::KB256;
+        let mut encoder =
+            LzxdEncoder::with_reference(window_size, CompressionLevel::DEFAULT, &reference)
+                .unwrap();
+        encoder.set_e8_translation(Some(subject.len() as u32));
+        let compressed = encoder.compress_chunks(&subject);
+
+        let mut lzxd = Lzxd::with_reference(window_size, &reference).unwrap();
+        for (chunk, data) in compressed.iter().zip(subject.chunks(MAX_CHUNK_SIZE)) {
+            assert_eq!(lzxd.decompress_next(chunk, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn reference_too_long() {
+        let reference = vec![0; WindowSize::KB32.value() + 1];
+        assert_eq!(
+            LzxdEncoder::with_reference(WindowSize::KB32, CompressionLevel::DEFAULT, &reference)
+                .err(),
+            Some(CompressError::WindowTooSmall)
+        );
+    }
+}
@@ -0,0 +1,94 @@
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+use crate::MAX_CHUNK_SIZE;
+
+/// The way compressed chunks are delimited in a stream.
+///
+/// > The LZXD engine encodes a compressed, chunk-size prefix field preceding each compressed
+/// > chunk in the compressed byte stream. The compressed, chunk-size prefix field is a byte
+/// > aligned, little-endian, 16-bit field.
+///
+/// Not every container follows this to the letter, so the framing must be known beforehand.
+/// In any case, every chunk represents exactly [`MAX_CHUNK_SIZE`] bytes of uncompressed data
+/// until the last chunk in the stream, unless the framing says otherwise.
+///
+/// [`MAX_CHUNK_SIZE`]: constant.MAX_CHUNK_SIZE.html
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Framing {
+    /// Each chunk is preceded by its compressed size as a little-endian 16-bit field, as
+    /// described in MS-PATCH.
+    MsPatch,
+
+    /// Each chunk is preceded by its compressed size as a big-endian 16-bit field. If the
+    /// first byte is 0xFF, it is instead followed by the uncompressed size and then the
+    /// compressed size, both as big-endian 16-bit fields. This is used by XNB files.
+    Xnb,
+}
+
+/// The sizes read from the header preceding a compressed chunk.
+pub(crate) struct ChunkHeader {
+    /// Size of the compressed chunk following the header.
+    pub compressed_len: usize,
+
+    /// Size of the chunk once decompressed, if the header included it.
+    pub uncompressed_len: Option<usize>,
+}
+
+impl Framing {
+    /// The longest a chunk header can be.
+    #[cfg(feature = "std")]
+    pub(crate) const MAX_HEADER_LEN: usize = 5;
+
+    /// Determines the length of a chunk header given its first byte.
+    pub(crate) fn header_len(&self, first: u8) -> usize {
+        match self {
+            Framing::MsPatch => 2,
+            Framing::Xnb if first == 0xFF => 5,
+            Framing::Xnb => 2,
+        }
+    }
+
+    /// Parses a chunk header, which must be exactly as long as [`Self::header_len`] says.
+    pub(crate) fn parse_header(&self, header: &[u8]) -> ChunkHeader {
+        match (self, header) {
+            (Framing::MsPatch, &[lo, hi]) => ChunkHeader {
+                compressed_len: u16::from_le_bytes([lo, hi]) as usize,
+                uncompressed_len: None,
+            },
+            (Framing::Xnb, &[0xFF, uhi, ulo, chi, clo]) => ChunkHeader {
+                compressed_len: u16::from_be_bytes([chi, clo]) as usize,
+                uncompressed_len: Some(u16::from_be_bytes([uhi, ulo]) as usize),
+            },
+            (Framing::Xnb, &[hi, lo]) => ChunkHeader {
+                compressed_len: u16::from_be_bytes([hi, lo]) as usize,
+                uncompressed_len: None,
+            },
+            _ => panic!("chunk header has the wrong length"),
+        }
+    }
+
+    /// Writes the header preceding a compressed chunk of the given sizes, the counterpart of
+    /// [`Self::parse_header`].
+    #[cfg(feature = "std")]
+    pub(crate) fn write_header(
+        &self,
+        compressed_len: usize,
+        uncompressed_len: usize,
+        out: &mut Vec<u8>,
+    ) {
+        let compressed_len = compressed_len as u16;
+        match self {
+            Framing::MsPatch => out.extend(compressed_len.to_le_bytes()),
+            Framing::Xnb if uncompressed_len == MAX_CHUNK_SIZE => {
+                out.extend(compressed_len.to_be_bytes())
+            }
+            Framing::Xnb => {
+                out.push(0xFF);
+                out.extend((uncompressed_len as u16).to_be_bytes());
+                out.extend(compressed_len.to_be_bytes());
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! Construction of the Huffman trees written by the encoder.
+//!
+//! > an LZXD decoder uses only the path lengths of the Huffman tree to reconstruct the
+//! > identical tree,
+//!
+//! so all the encoder needs to decide are the path lengths of every element, from which the
+//! codes themselves follow (in the same way the decoder assigns them).
+use alloc::{vec, vec::Vec};
+
+/// Computes the path lengths of a Huffman tree for elements with the given frequencies, with
+/// none longer than `max_len`.
+///
+/// Elements with a frequency of zero are left out of the tree (with a path length of zero).
+/// The tree is always complete, so if fewer than two elements are present, elements with a
+/// zero frequency are added until there are two.
+///
+/// The path lengths are optimal for the limit, as found by the package-merge algorithm. The
+/// main and length trees of LZXD are limited to 16 bits, the pretree to 15 (its path lengths
+/// are written using 4 bits), and the aligned offset tree to 7 (written using 3 bits).
+///
+/// # Panics
+///
+/// Panics if there are fewer than two elements, or more elements with a non-zero frequency
+/// than `max_len` bits can tell apart.
+///
+/// ```
+/// use ::lzxd::huffman;
+///
+/// let lengths = huffman::path_lengths(&[5, 0, 9, 12, 13, 16, 45, 0], 16);
+/// assert_eq!(lengths, [4, 0, 4, 3, 3, 3, 1, 0]);
+/// assert_eq!(huffman::codes(&lengths), [0b1110, 0, 0b1111, 0b100, 0b101, 0b110, 0b0, 0]);
+/// ```
+pub fn path_lengths(freqs: &[u32], max_len: u8) -> Vec<u8> {
+    assert!(freqs.len() >= 2);
+
+    let mut freqs = freqs.to_vec();
+    let present = freqs.iter().filter(|&&f| f != 0).count();
+    if present < 2 {
+        freqs
+            .iter_mut()
+            .filter(|f| **f == 0)
+            .take(2 - present)
+            .for_each(|f| *f = 1);
+    }
+
+    let mut leaves = freqs
+        .iter()
+        .enumerate()
+        .filter(|(_, &f)| f != 0)
+        .map(|(i, &f)| (f as u64, i))
+        .collect::<Vec<_>>();
+    leaves.sort_unstable();
+    let leaf_count = leaves.len();
+    assert!(leaf_count <= 1 << max_len);
+
+    // Every level holds the leaves merged with the packages made by pairing up the items of
+    // the level below it, from lightest to heaviest. Only whether each item is a leaf needs
+    // to be remembered, because the leaves of every level come in the same order.
+    let mut levels = Vec::with_capacity(max_len as usize);
+    let mut weights = Vec::<u64>::new();
+    for _ in 0..max_len {
+        let packages = weights
+            .chunks_exact(2)
+            .map(|pair| pair[0] + pair[1])
+            .collect::<Vec<_>>();
+
+        let mut is_leaf = Vec::with_capacity(leaf_count + packages.len());
+        weights.clear();
+        let (mut leaf, mut package) = (0, 0);
+        while leaf < leaf_count || package < packages.len() {
+            if package == packages.len()
+                || (leaf < leaf_count && leaves[leaf].0 <= packages[package])
+            {
+                weights.push(leaves[leaf].0);
+                is_leaf.push(true);
+                leaf += 1;
+            } else {
+                weights.push(packages[package]);
+                is_leaf.push(false);
+                package += 1;
+            }
+        }
+        levels.push(is_leaf);
+    }
+
+    // The lightest items of the top level make up the tree. Every leaf among them adds one
+    // to the path length of its element, and every package, one to those of the two items
+    // it was made of in the level below.
+    let mut depths = vec![0u8; leaf_count];
+    let mut taken = 2 * leaf_count - 2;
+    for is_leaf in levels.iter().rev() {
+        let leaves_taken = is_leaf[..taken].iter().filter(|&&leaf| leaf).count();
+        depths[..leaves_taken]
+            .iter_mut()
+            .for_each(|depth| *depth += 1);
+        taken = 2 * (taken - leaves_taken);
+    }
+
+    let mut lengths = vec![0; freqs.len()];
+    leaves
+        .iter()
+        .zip(&depths)
+        .for_each(|(&(_, i), &depth)| lengths[i] = depth);
+    lengths
+}
+
+/// Assigns the canonical code to every element with the given path lengths, the same way the
+/// decoder does: elements with shorter paths come first, and ties are broken by their index.
+pub fn codes(path_lengths: &[u8]) -> Vec<u16> {
+    let max_len = path_lengths.iter().copied().max().unwrap_or(0);
+
+    let mut codes = vec![0; path_lengths.len()];
+    let mut next_code = 0u32;
+    for len in 1..=max_len {
+        for (code, _) in codes
+            .iter_mut()
+            .zip(path_lengths)
+            .filter(|(_, &l)| l == len)
+        {
+            *code = next_code as u16;
+            next_code += 1;
+        }
+        next_code <<= 1;
+    }
+    codes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CanonicalTree;
+
+    fn kraft_sum(lengths: &[u8], max_len: u8) -> u32 {
+        lengths
+            .iter()
+            .filter(|&&len| len != 0)
+            .map(|&len| 1 << (max_len - len))
+            .sum()
+    }
+
+    #[test]
+    fn lengths_are_complete() {
+        let freqs = [5, 0, 9, 12, 13, 16, 45, 0];
+        let lengths = path_lengths(&freqs, 16);
+        assert_eq!(lengths, [4, 0, 4, 3, 3, 3, 1, 0]);
+        assert_eq!(kraft_sum(&lengths, 16), 1 << 16);
+    }
+
+    #[test]
+    fn lengths_are_limited() {
+        // Fibonacci frequencies produce the deepest trees possible.
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            freqs.push(freqs[freqs.len() - 1] + freqs[freqs.len() - 2]);
+        }
+        assert!(path_lengths(&freqs, 32).iter().any(|&len| len > 16));
+
+        let lengths = path_lengths(&freqs, 16);
+        assert!(lengths.iter().all(|&len| len > 0 && len <= 16));
+        assert_eq!(kraft_sum(&lengths, 16), 1 << 16);
+    }
+
+    #[test]
+    fn limited_lengths_are_optimal() {
+        assert_eq!(path_lengths(&[1, 1, 2, 4, 8], 16), [4, 4, 3, 2, 1]);
+        assert_eq!(path_lengths(&[1, 1, 2, 4, 8], 3), [3, 3, 3, 3, 1]);
+        assert_eq!(path_lengths(&[1, 1, 2, 4], 2), [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn lzxd_tree_limits() {
+        // The pretree path lengths are written using 4 bits each, and the aligned offset tree
+        // ones using 3 bits each.
+        let freqs = (0..20).map(|i| 1 << i).collect::<Vec<_>>();
+        let pretree = path_lengths(&freqs, 15);
+        assert!(pretree.iter().all(|&len| len > 0 && len <= 15));
+        assert_eq!(kraft_sum(&pretree, 15), 1 << 15);
+
+        let aligned = path_lengths(&freqs[..8], 7);
+        assert_eq!(aligned, [7, 7, 6, 5, 4, 3, 2, 1]);
+
+        let aligned = path_lengths(&freqs[..8], 3);
+        assert_eq!(aligned, [3; 8]);
+    }
+
+    #[test]
+    fn unused_elements_are_not_limited() {
+        // Only the elements present need to fit in the limit.
+        let mut freqs = vec![0; 256 + 8 * 50];
+        freqs[10] = 3;
+        freqs[300] = 1;
+        freqs[500] = 1;
+        let lengths = path_lengths(&freqs, 2);
+        assert_eq!((lengths[10], lengths[300], lengths[500]), (1, 2, 2));
+        assert_eq!(kraft_sum(&lengths, 2), 1 << 2);
+    }
+
+    #[test]
+    #[should_panic]
+    fn too_many_elements_for_the_limit() {
+        path_lengths(&[1, 1, 1, 1, 1], 2);
+    }
+
+    #[test]
+    fn decoder_accepts_lengths() {
+        let mut seed = 1u32;
+        for count in [8, 20, 249, 256 + 8 * 50] {
+            let freqs = (0..count)
+                .map(|_| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    // Mostly rare elements, with a few very common ones.
+                    let f = (seed >> 16) % 64;
+                    if f < 16 {
+                        0
+                    } else {
+                        f << (seed % 16)
+                    }
+                })
+                .collect::<Vec<_>>();
+            let lengths = path_lengths(&freqs, 16);
+            assert!(lengths.iter().all(|&len| len <= 16));
+            assert!(CanonicalTree::from_path_lengths(lengths)
+                .create_instance()
+                .is_ok());
+        }
+    }
+
+    #[test]
+    fn single_element_gets_a_sibling() {
+        let lengths = path_lengths(&[0, 0, 7, 0], 16);
+        assert_eq!(lengths, [1, 0, 1, 0]);
+
+        let lengths = path_lengths(&[0, 0, 0, 0], 16);
+        assert_eq!(lengths, [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn canonical_codes() {
+        assert_eq!(codes(&[2, 1, 3, 3]), [0b10, 0b0, 0b110, 0b111]);
+        assert_eq!(codes(&[0, 1, 0, 1]), [0, 0b0, 0, 0b1]);
+    }
+}
@@ -0,0 +1,90 @@
+/// The type of a block, which determines how its data is encoded.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum BlockType {
+    /// A block of literals and matches, with the match offsets stored verbatim.
+    Verbatim,
+
+    /// A block of literals and matches, with the low 3 bits of match offsets encoded using the
+    /// aligned offset tree.
+    AlignedOffset,
+
+    /// A block of uncompressed data.
+    Uncompressed,
+}
+
+/// The path lengths of the Huffman trees used by a block, after applying the deltas against
+/// the previous trees.
+///
+/// > Each tree element can have a path length of [0, 16], where a zero path length indicates
+/// > that the element has a zero frequency and is not present in the tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockTrees<'a> {
+    pub(crate) aligned_offset: Option<&'a [u8]>,
+    pub(crate) main: Option<&'a [u8]>,
+    pub(crate) length: Option<&'a [u8]>,
+}
+
+impl<'a> BlockTrees<'a> {
+    /// The path lengths of the 8 elements of the aligned offset tree, only present in aligned
+    /// offset blocks.
+    pub fn aligned_offset(&self) -> Option<&'a [u8]> {
+        self.aligned_offset
+    }
+
+    /// The path lengths of the main tree, with 256 elements for the literals followed by 8
+    /// elements per position slot for the matches. Not present in uncompressed blocks.
+    pub fn main(&self) -> Option<&'a [u8]> {
+        self.main
+    }
+
+    /// The path lengths of the 249 elements of the length tree. Not present in uncompressed
+    /// blocks, nor in blocks where the length tree is empty.
+    pub fn length(&self) -> Option<&'a [u8]> {
+        self.length
+    }
+}
+
+/// Read-only information about a block of the data stream, useful to analyze and compare the
+/// output of different compressors.
+///
+/// It can be obtained for the block currently being decoded with [`Lzxd::current_block`], or
+/// for every block as soon as it starts with [`Visitor::on_block_start`].
+///
+/// [`Lzxd::current_block`]: struct.Lzxd.html#method.current_block
+/// [`Visitor::on_block_start`]: trait.Visitor.html#method.on_block_start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo<'a> {
+    pub(crate) block_type: BlockType,
+    pub(crate) size: u32,
+    pub(crate) remaining: u32,
+    pub(crate) trees: BlockTrees<'a>,
+    pub(crate) repeated_offsets: Option<[u32; 3]>,
+}
+
+impl<'a> BlockInfo<'a> {
+    /// The type of the block.
+    pub fn block_type(&self) -> BlockType {
+        self.block_type
+    }
+
+    /// The size of the block once decompressed, as declared in its header.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// How many bytes of the block are left to be decompressed.
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// The path lengths of the trees the block is encoded with.
+    pub fn trees(&self) -> BlockTrees<'a> {
+        self.trees
+    }
+
+    /// The values of the repeated offsets R0, R1 and R2 stored in the header of uncompressed
+    /// blocks, or `None` for other block types.
+    pub fn repeated_offsets(&self) -> Option<[u32; 3]> {
+        self.repeated_offsets
+    }
+}
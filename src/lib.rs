@@ -8,34 +8,109 @@
 //! Lempel-Ziv Extended Delta (LZXD) is a derivative of the Lempel-Ziv Extended (LZX) format with
 //! some modifications to facilitate efficient delta compression.
 //!
-//! In order to use this module, refer to the main [`Lzxd`] type and its methods.
+//! In order to use this module, refer to the main [`Lzxd`] type and its methods, or wrap a
+//! framed stream of compressed chunks with [`LzxdReader`]. To compress data, refer to the
+//! [`LzxdEncoder`] type instead, or write the data to an [`LzxdWriter`] to produce a framed
+//! stream. The [`huffman`] module builds the length-limited trees the encoder needs, for
+//! those producing LZXD data by other means.
+//!
+//! # Containers
+//!
+//! LZX compressed data is usually found inside other file formats, which delimit the chunks
+//! in their own way. Some of them are supported by the following modules:
+//!
+//! * [`cab`]: Microsoft Cabinet files, whose folders may be LZX compressed.
+//! * [`chm`]: Compiled HTML Help files, whose compressed section is LZX compressed.
+//! * [`oab`]: Exchange Offline Address Book files and their patches, which are LZX DELTA
+//!   compressed.
+//! * [`wim`]: Windows Imaging files, whose resources may be LZX compressed.
+//! * [`xnb`]: XNB files produced by XNA Game Studio, whose content may be LZX compressed.
+//!
+//! # Features
+//!
+//! The `std` feature is enabled by default. Disabling it makes the crate `no_std`, only
+//! depending on `core` and `alloc`, at the cost of [`LzxdReader`] and [`LzxdWriter`], which
+//! need `std::io`.
 //!
 //! [LZX DELTA Compression and Decompression]: https://docs.microsoft.com/en-us/openspecs/exchange_server_protocols/ms-patch/cc78752a-b4af-4eee-88cb-01f4d8a4c2bf
 //! [UASDC]: https://ieeexplore.ieee.org/document/1055714
 //! [`Lzxd`]: struct.Lzxd.html
-use std::{fmt, mem};
+//! [`LzxdReader`]: struct.LzxdReader.html
+//! [`LzxdEncoder`]: struct.LzxdEncoder.html
+//! [`LzxdWriter`]: struct.LzxdWriter.html
+//! [`huffman`]: huffman/index.html
+//! [`cab`]: cab/index.html
+//! [`chm`]: chm/index.html
+//! [`oab`]: oab/index.html
+//! [`wim`]: wim/index.html
+//! [`xnb`]: xnb/index.html
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::{fmt, mem};
 
 pub(crate) use bitstream::Bitstream;
+pub use bitwriter::BitWriter;
 pub(crate) use block::{Block, Decoded, Kind as BlockKind};
+pub use encoder::{CompressError, CompressionLevel, LzxdEncoder};
+pub use framing::Framing;
+pub use info::{BlockInfo, BlockTrees, BlockType};
+#[cfg(feature = "std")]
+pub use reader::LzxdReader;
+pub use snapshot::{LzxdSnapshot, SnapshotError};
 pub(crate) use tree::{CanonicalTree, Tree};
-use window::Window;
+pub use visitor::Visitor;
 pub use window::WindowSize;
+use window::{FlatWindow, Output, Window};
+#[cfg(feature = "std")]
+pub use writer::LzxdWriter;
 
 mod bitstream;
+mod bitwriter;
 mod block;
+mod bytes;
+pub mod cab;
+pub mod chm;
+mod encoder;
+mod framing;
+pub mod huffman;
+mod info;
+mod matchfinder;
+pub mod oab;
+mod optimal;
+#[cfg(feature = "std")]
+mod reader;
+mod snapshot;
+mod split;
 mod tree;
+mod visitor;
+pub mod wim;
 mod window;
+#[cfg(feature = "std")]
+mod writer;
+pub mod xnb;
 
 /// A chunk represents exactly 32 KB of uncompressed data until the last chunk in the stream,
 /// which can represent less than 32 KB.
 pub const MAX_CHUNK_SIZE: usize = 32 * 1024;
 
-/// Decoder state needed for new blocks.
-// TODO not sure how much we want to keep in DecoderState and Lzxd respectively
+/// The translation size that [`Variant::Wim`] streams always use for E8 translation.
+pub(crate) const WIM_E8_TRANSLATION_SIZE: i32 = 12_000_000;
+
+/// The size of the blocks that [`Variant::Wim`] streams flag with a single bit.
+pub(crate) const WIM_DEFAULT_BLOCK_SIZE: u32 = 32 * 1024;
+
+/// Decoder state, which is everything needed to decode chunks other than the window itself.
+#[derive(Clone)]
 pub(crate) struct DecoderState {
     /// The window size we're working with.
     window_size: WindowSize,
 
+    /// The format variant we're decoding.
+    variant: Variant,
+
     /// This tree cannot be used directly, it exists only to apply the delta of upcoming trees
     /// to its path lengths.
     main_tree: CanonicalTree,
@@ -43,8 +118,31 @@ pub(crate) struct DecoderState {
     /// This tree cannot be used directly, it exists only to apply the delta of upcoming trees
     /// to its path lengths.
     length_tree: CanonicalTree,
+
+    /// > The three most recent real match offsets are kept in a list.
+    r: [u32; 3],
+
+    /// The current offset into the decompressed data.
+    chunk_offset: usize,
+
+    /// Has the very first chunk been read yet? Unlike the rest, it has additional data.
+    first_chunk_read: bool,
+
+    /// How many chunks have been decoded so far.
+    chunk_count: usize,
+
+    /// How many blocks have been started so far, including the current one.
+    block_count: usize,
+
+    /// Current block.
+    current_block: Block,
+
+    /// Information and data related to E8 postprocessing. This is populated after
+    /// the first chunk is read.
+    postprocess: Option<PostProcessState>,
 }
 
+#[derive(Clone)]
 struct PostProcessState {
     /// The pointer in the file at which to stop performing E8 translation.
     e8_translation_size: i32,
@@ -76,26 +174,35 @@ pub struct Lzxd {
 
     /// Current decoder state.
     state: DecoderState,
+}
 
-    /// > The three most recent real match offsets are kept in a list.
-    r: [u32; 3],
-
-    /// The current offset into the decompressed data.
-    chunk_offset: usize,
-
-    /// Has the very first chunk been read yet? Unlike the rest, it has additional data.
-    first_chunk_read: bool,
-
-    /// Current block.
-    current_block: Block,
-
-    /// Information and data related to E8 postprocessing. This is populated after
-    /// the first chunk is read.
-    postprocess: Option<PostProcessState>,
+/// The variant of the format that the compressed data stream uses.
+///
+/// Like the [`WindowSize`], this is not stored in the compressed data stream and must be known
+/// before decoding begins.
+///
+/// [`WindowSize`]: enum.WindowSize.html
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Variant {
+    /// Classic LZX, as found in XNB and CAB files, where matches are at most 257 bytes long.
+    Lzx,
+
+    /// LZX DELTA, as described in MS-PATCH, where matches of 257 bytes or longer are followed
+    /// by an Extra Length field.
+    LzxDelta,
+
+    /// LZX as found in WIM files, where E8 translation is always enabled with a translation
+    /// size of 12000000 and the stream doesn't start with a header saying so. Blocks of 32 KB
+    /// have their size replaced by a single bit, and the size of the rest takes 16 bits if
+    /// the window is 32 KB.
+    Wim,
 }
 
 /// Specific cause for decompression failure.
+///
+/// More causes may be told apart in the future, so matching on it must have a catch-all arm.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum DecodeFailed {
     /// The chunk data caused a read of more items than the current block had in a single step.
     OverreadBlock,
@@ -124,6 +231,13 @@ pub enum DecodeFailed {
     /// The given window size was too small.
     WindowTooSmall,
 
+    /// The chunk data caused a read of more items than the chunk had in a single step.
+    OverreadChunk,
+
+    /// A match referred to data before the start of the decompressed data or farther back
+    /// than the window size allows.
+    InvalidMatchOffset(usize),
+
     /// Tried to read a chunk longer than [`MAX_CHUNK_SIZE`].
     ///
     /// [`MAX_CHUNK_SIZE`]: constant.MAX_CHUNK_SIZE.html
@@ -147,6 +261,11 @@ impl fmt::Display for DecodeFailed {
             InvalidPathLengths => write!(f, "encountered invalid path lengths"),
             EmptyTree => write!(f, "encountered empty decode tree"),
             WindowTooSmall => write!(f, "decode window was too small"),
+            OverreadChunk => write!(
+                f,
+                "read more items than available in the chunk in a single step"
+            ),
+            InvalidMatchOffset(offset) => write!(f, "match offset {} is invalid", offset),
             ChunkTooLong => write!(
                 f,
                 "tried reading a chunk longer than {} bytes",
@@ -156,33 +275,93 @@ impl fmt::Display for DecodeFailed {
     }
 }
 
-impl std::error::Error for DecodeFailed {}
+impl core::error::Error for DecodeFailed {}
 
 /// The error type used when decompression fails.
+///
+/// Along with the specific cause of the failure, it records where in the data stream the
+/// failure happened.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub struct DecompressError(DecodeFailed);
+pub struct DecompressError {
+    kind: DecodeFailed,
+    chunk_index: usize,
+    bit_position: usize,
+    block_index: usize,
+    output_offset: usize,
+}
+
+impl DecompressError {
+    /// The specific cause for the failure.
+    pub fn kind(&self) -> DecodeFailed {
+        self.kind
+    }
+
+    /// The index of the chunk being decompressed, starting at zero with the first chunk of the
+    /// data stream.
+    pub fn chunk_index(&self) -> usize {
+        self.chunk_index
+    }
+
+    /// How many whole bytes of the compressed chunk had been read when decoding stopped.
+    pub fn byte_offset(&self) -> usize {
+        self.bit_position / 8
+    }
+
+    /// How many bits past [`Self::byte_offset`] had been read when decoding stopped, in the
+    /// order the bitstream is read.
+    pub fn bit_offset(&self) -> u8 {
+        (self.bit_position % 8) as u8
+    }
+
+    /// The index of the block being decoded (or whose header was being read), starting at zero
+    /// with the first block of the data stream.
+    pub fn block_index(&self) -> usize {
+        self.block_index
+    }
+
+    /// The offset into the decompressed data where the element being decoded would have been
+    /// written.
+    pub fn output_offset(&self) -> usize {
+        self.output_offset
+    }
+}
 
 impl fmt::Display for DecompressError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        self.0.fmt(f)
+        write!(
+            f,
+            "{} (chunk {}, byte {}, bit {}, block {}, output offset {})",
+            self.kind,
+            self.chunk_index,
+            self.byte_offset(),
+            self.bit_offset(),
+            self.block_index,
+            self.output_offset
+        )
     }
 }
 
-impl std::error::Error for DecompressError {}
+impl core::error::Error for DecompressError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        Some(&self.kind)
+    }
+}
 
+/// Errors that happen before any data is decoded are reported at the very start of the stream.
 impl From<DecodeFailed> for DecompressError {
-    fn from(value: DecodeFailed) -> Self {
-        Self(value)
+    fn from(kind: DecodeFailed) -> Self {
+        Self {
+            kind,
+            chunk_index: 0,
+            bit_position: 0,
+            block_index: 0,
+            output_offset: 0,
+        }
     }
 }
 
-impl Lzxd {
-    /// Creates a new instance of the LZXD decoder state. The [`WindowSize`] must be obtained
-    /// from elsewhere (e.g. it may be predetermined to a certain value), and if it's wrong,
-    /// the decompressed values won't be those expected.
-    ///
-    /// [`WindowSize`]: enum.WindowSize.html
-    pub fn new(window_size: WindowSize) -> Self {
+impl DecoderState {
+    fn new(window_size: WindowSize, variant: Variant) -> Self {
         // > The main tree comprises 256 elements that correspond to all possible 8-bit
         // > characters, plus 8 * NUM_POSITION_SLOTS elements that correspond to matches.
         let main_tree = CanonicalTree::new(256 + 8 * window_size.position_slots());
@@ -191,20 +370,20 @@ impl Lzxd {
         let length_tree = CanonicalTree::new(249);
 
         Self {
-            window: window_size.create_buffer(),
+            window_size,
+            variant,
             // > Because trees are output several times during compression of large amounts of
             // > data (multiple blocks), LZXD optimizes compression by encoding only the delta
             // > path lengths lengths between the current and previous trees.
             //
             // Because it uses deltas, we need to store the previous value across blocks.
-            state: DecoderState {
-                window_size,
-                main_tree,
-                length_tree,
-            },
+            main_tree,
+            length_tree,
             // > The initial state of R0, R1, R2 is (1, 1, 1).
             r: [1, 1, 1],
             first_chunk_read: false,
+            chunk_count: 0,
+            block_count: 0,
             chunk_offset: 0,
             postprocess: None,
             // Start with some dummy value.
@@ -225,71 +404,32 @@ impl Lzxd {
         if !self.first_chunk_read {
             self.first_chunk_read = true;
 
-            let e8_translation = bitstream.read_bit()? != 0;
-            self.postprocess = if e8_translation {
-                Some(PostProcessState {
-                    data_chunk: vec![0; MAX_CHUNK_SIZE].into_boxed_slice(),
-                    e8_translation_size: bitstream.read_bits(32)? as i32,
-                })
-            } else {
-                None
+            // WIM streams leave the header out, as E8 translation is always enabled for them.
+            let e8_translation_size = match self.variant {
+                Variant::Wim => Some(WIM_E8_TRANSLATION_SIZE),
+                Variant::Lzx | Variant::LzxDelta => match bitstream.read_bit()? {
+                    0 => None,
+                    _ => Some(bitstream.read_bits(32)? as i32),
+                },
             };
+            self.postprocess = e8_translation_size.map(|e8_translation_size| PostProcessState {
+                data_chunk: vec![0; MAX_CHUNK_SIZE].into_boxed_slice(),
+                e8_translation_size,
+            });
         }
 
         Ok(())
     }
 
-    /// Attempts to perform post-decompression E8 fixups on an output data buffer.
-    fn postprocess(
-        translation_size: i32,
-        chunk_offset: usize,
-        idata: &mut [u8],
-    ) -> Result<&[u8], DecodeFailed> {
-        let mut processed = 0usize;
-
-        // Find the next E8 match, or finish once there are no more E8 matches.
-        while let Some(pos) = idata[processed..]
-            .iter()
-            .position(|&e| e == 0xE8)
-            .map(|pos| processed + pos)
-        {
-            // N.B: E8 fixups are only performed for up to 10 bytes before the end of a chunk.
-            if idata.len() - pos <= 10 {
-                break;
-            }
-
-            // This is the current file output pointer.
-            let current_pointer = chunk_offset + pos;
-
-            // Match. Fix up the following bytes.
-            let abs_val = i32::from_le_bytes([
-                idata[pos + 1],
-                idata[pos + 2],
-                idata[pos + 3],
-                idata[pos + 4],
-            ]);
-            if (abs_val >= -(current_pointer as i32)) && abs_val < translation_size {
-                let rel_val = if abs_val.is_positive() {
-                    abs_val.wrapping_sub(current_pointer as i32)
-                } else {
-                    abs_val.wrapping_add(translation_size)
-                };
-
-                idata[pos + 1..pos + 5].copy_from_slice(&rel_val.to_le_bytes());
-            }
-
-            processed = pos + 5;
-        }
-
-        Ok(idata)
-    }
-
-    /// Decompresses the next compressed `chunk` from the LZXD data stream.
-    pub fn decompress_next(
+    /// Decodes the next compressed `chunk` from the LZXD data stream into the `output`, without
+    /// performing any postprocessing. Returns the offset of the chunk in the decompressed data.
+    fn decode_next<O: Output, V: Visitor>(
         &mut self,
+        output: &mut O,
         chunk: &[u8],
         output_len: usize,
-    ) -> Result<&[u8], DecompressError> {
+        visitor: &mut V,
+    ) -> Result<usize, DecompressError> {
         // > A chunk represents exactly 32 KB of uncompressed data until the last chunk in the
         // > stream, which can represent less than 32 KB.
         //
@@ -304,11 +444,37 @@ impl Lzxd {
         // TODO maybe the docs could clarify whether this length is compressed or not
 
         let mut bitstream = Bitstream::new(chunk);
+        let mut decoded_len = 0;
+        if let Err(kind) = self.decode_chunk(
+            &mut bitstream,
+            output,
+            output_len,
+            visitor,
+            &mut decoded_len,
+        ) {
+            return Err(self.error(kind, bitstream.bit_position(), decoded_len));
+        }
 
-        self.try_read_first_chunk(&mut bitstream)?;
+        let chunk_offset = self.chunk_offset;
+        self.chunk_offset += decoded_len;
+        self.chunk_count += 1;
 
-        let mut decoded_len = 0;
-        while decoded_len != output_len {
+        Ok(chunk_offset)
+    }
+
+    /// Decodes `output_len` bytes from the `bitstream` of a chunk into the `output`, keeping
+    /// track of the progress in `decoded_len` so that it's known on failure.
+    fn decode_chunk<O: Output, V: Visitor>(
+        &mut self,
+        bitstream: &mut Bitstream,
+        output: &mut O,
+        output_len: usize,
+        visitor: &mut V,
+        decoded_len: &mut usize,
+    ) -> Result<(), DecodeFailed> {
+        self.try_read_first_chunk(bitstream)?;
+
+        while *decoded_len != output_len {
             if self.current_block.remaining == 0 {
                 // Re-align the bitstream to word
                 // Related: https://github.com/GNOME/gcab/blob/master/libgcab/decomp.c#L883.
@@ -318,52 +484,222 @@ impl Lzxd {
                 {
                     bitstream.read_byte();
                 }
-                self.current_block = Block::read(&mut bitstream, &mut self.state)?;
+                self.block_count += 1;
+                self.current_block = Block::read(bitstream, self)?;
                 assert_ne!(self.current_block.remaining, 0);
+                visitor.on_block_start(&self.current_block.info());
             }
 
-            let decoded = self
-                .current_block
-                .decode_element(&mut bitstream, &mut self.r)?;
+            let decoded =
+                self.current_block
+                    .decode_element(bitstream, &mut self.r, self.variant)?;
 
             let advance = match decoded {
                 Decoded::Single(value) => {
-                    self.window.push(value);
+                    visitor.on_literal(value);
+                    output.push(value)?;
                     1
                 }
-                Decoded::Match { offset, length } => {
-                    self.window.copy_from_self(offset, length);
+                Decoded::Match {
+                    offset,
+                    length,
+                    repeated_slot,
+                } => {
+                    visitor.on_match(offset, length, repeated_slot);
+                    // Matches may not go past the end of the chunk.
+                    if length > output_len - *decoded_len {
+                        return Err(DecodeFailed::OverreadChunk);
+                    }
+                    output.copy_from_self(offset, length)?;
                     length
                 }
                 Decoded::Read(length) => {
                     // Read up to end of chunk, to allow for larger blocks.
-                    let length = usize::min(bitstream.remaining_bytes(), length);
+                    let length = length
+                        .min(bitstream.remaining_bytes())
+                        .min(output_len - *decoded_len);
                     // Will re-align if needed, just as decompressed reads mandate.
-                    self.window.copy_from_bitstream(&mut bitstream, length)?;
+                    output.copy_from_bitstream(bitstream, length)?;
+                    visitor.on_uncompressed(length);
                     length
                 }
             };
 
             assert_ne!(advance, 0);
-            decoded_len += advance;
+            *decoded_len += advance;
             if let Some(value) = self.current_block.remaining.checked_sub(advance as u32) {
                 self.current_block.remaining = value;
             } else {
-                return Err(DecodeFailed::OverreadBlock.into());
+                return Err(DecodeFailed::OverreadBlock);
             }
         }
 
-        let chunk_offset = self.chunk_offset;
-        self.chunk_offset += decoded_len;
+        Ok(())
+    }
 
-        let view = self.window.past_view(decoded_len)?;
-        if let Some(postprocess) = self.postprocess.as_mut() {
-            // E8 fixups are disabled after 1GB of input data,
-            // or if the chunk size is too small.
-            if chunk_offset >= 0x4000_0000 || decoded_len <= 10 {
-                Ok(view)
-            } else {
-                let postprocess_buf = &mut postprocess.data_chunk[..decoded_len];
+    /// Makes sure a chunk with the given decompressed length fits in the window, before
+    /// decoding it.
+    fn check_output_len(&self, output_len: usize) -> Result<(), DecompressError> {
+        if output_len > MAX_CHUNK_SIZE {
+            Err(self.error(DecodeFailed::ChunkTooLong, 0, 0))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Creates an error for a failure found `bit_position` bits into the current chunk, after
+    /// `decoded_len` bytes of it had been decoded.
+    fn error(
+        &self,
+        kind: DecodeFailed,
+        bit_position: usize,
+        decoded_len: usize,
+    ) -> DecompressError {
+        DecompressError {
+            kind,
+            chunk_index: self.chunk_count,
+            bit_position,
+            block_index: self.block_count.saturating_sub(1),
+            output_offset: self.chunk_offset + decoded_len,
+        }
+    }
+}
+
+impl Lzxd {
+    /// Creates a new instance of the LZXD decoder state. The [`WindowSize`] must be obtained
+    /// from elsewhere (e.g. it may be predetermined to a certain value), and if it's wrong,
+    /// the decompressed values won't be those expected.
+    ///
+    /// This decodes the classic [`Variant::Lzx`] format. Use [`Self::with_variant`] to decode
+    /// other variants.
+    ///
+    /// [`WindowSize`]: enum.WindowSize.html
+    pub fn new(window_size: WindowSize) -> Self {
+        Self::with_variant(window_size, Variant::Lzx)
+    }
+
+    /// Creates a new instance of the decoder state for the given [`Variant`] of the format.
+    /// Both the [`WindowSize`] and the [`Variant`] must be obtained from elsewhere, and if
+    /// they're wrong, the decompressed values won't be those expected.
+    ///
+    /// [`WindowSize`]: enum.WindowSize.html
+    /// [`Variant`]: enum.Variant.html
+    pub fn with_variant(window_size: WindowSize, variant: Variant) -> Self {
+        Self {
+            window: window_size.create_buffer(),
+            state: DecoderState::new(window_size, variant),
+        }
+    }
+
+    /// Creates a new instance of the LZXD decoder state with its window pre-loaded with
+    /// `reference` data, as used by LZX DELTA to encode the subject data as matches into it.
+    /// The decoder uses the [`Variant::LzxDelta`] format.
+    ///
+    /// The reference data, rounded up to a multiple of 32 KB, must fit in the [`WindowSize`].
+    /// Ideally, there should also be room left for the subject data, or else the start of the
+    /// reference will be overwritten as the window slides.
+    ///
+    /// Note that [`Self::reset`] discards the reference data.
+    ///
+    /// [`WindowSize`]: enum.WindowSize.html
+    pub fn with_reference(
+        window_size: WindowSize,
+        reference: &[u8],
+    ) -> Result<Self, DecompressError> {
+        let mut this = Self::with_variant(window_size, Variant::LzxDelta);
+        this.window.load_reference(reference)?;
+        // E8 translation is defined in terms of the position in the decompressed output, which
+        // the reference is not part of, so the chunk offset stays at zero even though the
+        // window already contains data. libmspack's `lzxd_set_reference_data` does the same,
+        // and so does `LzxdEncoder::with_reference`.
+        Ok(this)
+    }
+
+    /// Attempts to perform post-decompression E8 fixups on an output data buffer.
+    fn postprocess<'a, V: Visitor>(
+        translation_size: i32,
+        chunk_offset: usize,
+        idata: &'a mut [u8],
+        visitor: &mut V,
+    ) -> &'a [u8] {
+        let mut processed = 0usize;
+
+        // Find the next E8 match, or finish once there are no more E8 matches.
+        while let Some(pos) = idata[processed..]
+            .iter()
+            .position(|&e| e == 0xE8)
+            .map(|pos| processed + pos)
+        {
+            // N.B: E8 fixups are only performed for up to 10 bytes before the end of a chunk.
+            if idata.len() - pos <= 10 {
+                break;
+            }
+
+            // This is the current file output pointer.
+            let current_pointer = chunk_offset + pos;
+
+            // Match. Fix up the following bytes.
+            let abs_val = i32::from_le_bytes([
+                idata[pos + 1],
+                idata[pos + 2],
+                idata[pos + 3],
+                idata[pos + 4],
+            ]);
+            if (abs_val >= -(current_pointer as i32)) && abs_val < translation_size {
+                let rel_val = if abs_val >= 0 {
+                    abs_val.wrapping_sub(current_pointer as i32)
+                } else {
+                    abs_val.wrapping_add(translation_size)
+                };
+
+                idata[pos + 1..pos + 5].copy_from_slice(&rel_val.to_le_bytes());
+                visitor.on_e8_fixup(current_pointer);
+            }
+
+            processed = pos + 5;
+        }
+
+        idata
+    }
+
+    /// Whether E8 fixups need to be performed on a chunk of the given length at the given
+    /// offset into the decompressed data.
+    fn needs_postprocess(chunk_offset: usize, len: usize) -> bool {
+        // E8 fixups are disabled after 1GB of input data,
+        // or if the chunk size is too small.
+        chunk_offset < 0x4000_0000 && len > 10
+    }
+
+    /// Decompresses the next compressed `chunk` from the LZXD data stream.
+    pub fn decompress_next(
+        &mut self,
+        chunk: &[u8],
+        output_len: usize,
+    ) -> Result<&[u8], DecompressError> {
+        self.decompress_next_with_visitor(chunk, output_len, &mut ())
+    }
+
+    /// Decompresses the next compressed `chunk` from the LZXD data stream, like
+    /// [`Self::decompress_next`] does, while reporting everything decoded to the `visitor`.
+    ///
+    /// See [`Visitor`] for an example.
+    ///
+    /// [`Visitor`]: trait.Visitor.html
+    pub fn decompress_next_with_visitor<V: Visitor>(
+        &mut self,
+        chunk: &[u8],
+        output_len: usize,
+        visitor: &mut V,
+    ) -> Result<&[u8], DecompressError> {
+        self.state.check_output_len(output_len)?;
+        let chunk_offset = self
+            .state
+            .decode_next(&mut self.window, chunk, output_len, visitor)?;
+
+        let view = self.window.past_view(output_len)?;
+        match self.state.postprocess.as_mut() {
+            Some(postprocess) if Self::needs_postprocess(chunk_offset, output_len) => {
+                let postprocess_buf = &mut postprocess.data_chunk[..output_len];
                 postprocess_buf.copy_from_slice(view);
 
                 // E8 fixups are enabled. Postprocess the output buffer.
@@ -371,28 +707,197 @@ impl Lzxd {
                     postprocess.e8_translation_size,
                     chunk_offset,
                     postprocess_buf,
-                )?;
+                    visitor,
+                );
                 Ok(view)
             }
+            _ => Ok(view),
+        }
+    }
+
+    /// Decompresses the next compressed `chunk` from the LZXD data stream directly into `out`,
+    /// whose length must be the size of the chunk once decompressed.
+    ///
+    /// Unlike [`Self::decompress_next`], the decompressed data does not borrow from the decoder,
+    /// so consecutive chunks can be written into the same preallocated buffer. Returns the
+    /// amount of bytes written, which is always the length of `out`.
+    ///
+    /// ```no_run
+    /// # fn get_compressed_chunk() -> Option<(Vec<u8>, usize)> { unimplemented!() }
+    /// # let total_len = 0;
+    /// use ::lzxd::{Lzxd, WindowSize};
+    ///
+    /// let mut lzxd = Lzxd::new(WindowSize::KB64);
+    /// let mut output = vec![0; total_len];
+    /// let mut pos = 0;
+    ///
+    /// while let Some((chunk, output_size)) = get_compressed_chunk() {
+    ///     pos += lzxd
+    ///         .decompress_next_into(&chunk, &mut output[pos..pos + output_size])
+    ///         .unwrap();
+    /// }
+    /// ```
+    pub fn decompress_next_into(
+        &mut self,
+        chunk: &[u8],
+        out: &mut [u8],
+    ) -> Result<usize, DecompressError> {
+        self.state.check_output_len(out.len())?;
+        let chunk_offset = self
+            .state
+            .decode_next(&mut self.window, chunk, out.len(), &mut ())?;
+
+        out.copy_from_slice(self.window.past_view(out.len())?);
+        if let Some(postprocess) = self.state.postprocess.as_ref() {
+            if Self::needs_postprocess(chunk_offset, out.len()) {
+                Self::postprocess(postprocess.e8_translation_size, chunk_offset, out, &mut ());
+            }
+        }
+
+        Ok(out.len())
+    }
+
+    /// Returns information about the block currently being decoded, which the next chunk will
+    /// continue, or `None` if the next chunk starts with a new block.
+    pub fn current_block(&self) -> Option<BlockInfo<'_>> {
+        if self.state.current_block.remaining == 0 {
+            None
         } else {
-            Ok(view)
+            Some(self.state.current_block.info())
         }
     }
 
     /// Resets the decoder state.
     ///
-    /// This is equivalent to calling [`Self::new`] with the same [`WindowSize`].
+    /// This is equivalent to calling [`Self::with_variant`] with the same [`WindowSize`] and
+    /// [`Variant`].
     /// [`WindowSize`]: enum.WindowSize.html
+    /// [`Variant`]: enum.Variant.html
     pub fn reset(&mut self) {
-        let this = Self::new(self.state.window_size);
+        let this = Self::with_variant(self.state.window_size, self.state.variant);
         let _ = mem::replace(self, this);
     }
+
+    /// Resets the decoder state like [`Self::reset`] does, but with the next chunk at the
+    /// given `position` of the decompressed data instead of at its start.
+    ///
+    /// E8 translation depends on the position of the data, so this is needed when the state
+    /// is reset in the middle of the data, as CHM files do every few chunks. Containers where
+    /// every reset starts a new piece of data, like WIM files, use [`Self::reset`] instead.
+    pub fn reset_at(&mut self, position: usize) {
+        self.reset();
+        self.state.chunk_offset = position;
+    }
+}
+
+/// Decompresses an entire framed LZXD data stream at once, when the `total_len` of the data
+/// once decompressed is known up front (for example, because a container header says so).
+///
+/// This is a faster alternative to feeding every chunk to [`Lzxd::decompress_next`]: instead
+/// of going through a sliding window, data is decoded straight into the returned buffer. As
+/// with [`Lzxd::with_variant`], the [`WindowSize`] and [`Variant`] must be known beforehand.
+///
+/// ```no_run
+/// # let input = &[];
+/// use ::lzxd::{Framing, Variant, WindowSize};
+///
+/// let decompressed =
+///     lzxd::decompress_all(input, Framing::Xnb, WindowSize::KB64, Variant::Lzx, 1234);
+/// ```
+///
+/// [`Lzxd::decompress_next`]: struct.Lzxd.html#method.decompress_next
+/// [`Lzxd::with_variant`]: struct.Lzxd.html#method.with_variant
+/// [`WindowSize`]: enum.WindowSize.html
+/// [`Variant`]: enum.Variant.html
+pub fn decompress_all(
+    mut input: &[u8],
+    framing: Framing,
+    window_size: WindowSize,
+    variant: Variant,
+    total_len: usize,
+) -> Result<Vec<u8>, DecompressError> {
+    let mut state = DecoderState::new(window_size, variant);
+    let mut output = FlatWindow::new(window_size, total_len);
+    // Matches copy the data of previous chunks as it was decoded, so E8 fixups can only be
+    // performed once every chunk has been decoded.
+    let mut chunk_lens = Vec::new();
+
+    while output.pos() != total_len {
+        let eof = || state.error(DecodeFailed::UnexpectedEof, 0, 0);
+        let header_len = framing.header_len(*input.first().ok_or_else(eof)?);
+        let header = framing.parse_header(input.get(..header_len).ok_or_else(eof)?);
+        input = &input[header_len..];
+
+        let chunk = input.get(..header.compressed_len).ok_or_else(eof)?;
+        input = &input[header.compressed_len..];
+
+        let output_len = header.uncompressed_len.unwrap_or(MAX_CHUNK_SIZE);
+        state.check_output_len(output_len)?;
+        let output_len = output_len.min(total_len - output.pos());
+        state.decode_next(&mut output, chunk, output_len, &mut ())?;
+        chunk_lens.push(output_len);
+    }
+
+    if let Some(postprocess) = state.postprocess.as_ref() {
+        let mut chunk_offset = 0;
+        for len in chunk_lens {
+            if Lzxd::needs_postprocess(chunk_offset, len) {
+                Lzxd::postprocess(
+                    postprocess.e8_translation_size,
+                    chunk_offset,
+                    &mut output.past_view()[chunk_offset..chunk_offset + len],
+                    &mut (),
+                );
+            }
+            chunk_offset += len;
+        }
+    }
+
+    Ok(output.into_inner())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    impl BitWriter {
+        /// Writes the path lengths for the first tree of a stream (whose deltas are against
+        /// all zeros) using a fixed pretree where elements 0 to 11 take 4 bits and the rest 5.
+        fn write_first_tree(&mut self, lengths: &[u8]) {
+            (0..20).for_each(|i| self.write_bits(if i < 12 { 4 } else { 5 }, 4));
+            for &length in lengths {
+                let code = (17 - length as u32) % 17;
+                if code < 12 {
+                    self.write_bits(code, 4);
+                } else {
+                    self.write_bits(0b11000 + code - 12, 5);
+                }
+            }
+        }
+
+        /// Writes the header of the first verbatim block of a stream, without E8 translation.
+        fn write_verbatim_header(
+            &mut self,
+            window_size: WindowSize,
+            size: u32,
+            main_lengths: &[(usize, u8)],
+            length_lengths: &[(usize, u8)],
+        ) {
+            let mut main = vec![0; 256 + 8 * window_size.position_slots()];
+            main_lengths.iter().for_each(|&(i, len)| main[i] = len);
+            let mut length = vec![0; 249];
+            length_lengths.iter().for_each(|&(i, len)| length[i] = len);
+
+            self.write_bits(0, 1);
+            self.write_bits(0b001, 3);
+            self.write_bits(size >> 8, 16);
+            self.write_bits(size & 0xff, 8);
+            self.write_first_tree(&main[..256]);
+            self.write_first_tree(&main[256..]);
+            self.write_first_tree(&length);
+        }
+    }
+
     #[test]
     fn check_uncompressed() {
         let data = [
@@ -421,24 +926,109 @@ mod tests {
         assert_eq!(res.unwrap(), [b'a', b'b', b'c']);
     }
 
+    #[test]
+    fn check_wim_uncompressed() {
+        // There's no E8 translation header, and the size of a block takes 16 bits unless it's
+        // the default size, in which case it takes a single bit.
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b011, 3);
+        writer.write_bit(false);
+        writer.write_bits(3, 16);
+        writer.align();
+        (0..3).for_each(|_| writer.write_u32_le(1));
+        writer.write_raw(b"abc");
+        let short = writer.finish();
+
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b011, 3);
+        writer.write_bit(true);
+        writer.align();
+        (0..3).for_each(|_| writer.write_u32_le(1));
+        writer.write_raw(&[b'd'; MAX_CHUNK_SIZE]);
+        let full = writer.finish();
+
+        let mut lzxd = Lzxd::with_variant(WindowSize::KB32, Variant::Wim);
+        assert_eq!(lzxd.decompress_next(&short, 3).unwrap(), b"abc");
+        lzxd.reset();
+        assert_eq!(
+            lzxd.decompress_next(&full, MAX_CHUNK_SIZE).unwrap(),
+            [b'd'; MAX_CHUNK_SIZE]
+        );
+    }
+
+    /// An uncompressed block of 20 bytes with E8 translation enabled, whose only call is at
+    /// position 4 to the absolute offset 0.
+    fn call_to_zero_stream() -> Vec<u8> {
+        let mut data = [0u8; 20];
+        data[4] = 0xE8;
+
+        let mut writer = BitWriter::new();
+        writer.write_bit(true);
+        writer.write_bits(1000, 32);
+        writer.write_bits(0b011, 3);
+        writer.write_u24_be(data.len() as u32);
+        writer.align();
+        (0..3).for_each(|_| writer.write_u32_le(1));
+        writer.write_raw(&data);
+        writer.finish()
+    }
+
+    #[test]
+    fn e8_translation_of_zero() {
+        // The call must become relative to its position.
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        let out = lzxd.decompress_next(&call_to_zero_stream(), 20).unwrap();
+        assert_eq!(out[5..9], (-4i32).to_le_bytes());
+    }
+
+    #[test]
+    fn e8_translation_after_reference() {
+        // Positions are counted from the start of the subject data, not of the reference.
+        let mut lzxd = Lzxd::with_reference(WindowSize::KB64, b"hello world").unwrap();
+        let out = lzxd.decompress_next(&call_to_zero_stream(), 20).unwrap();
+        assert_eq!(out[5..9], (-4i32).to_le_bytes());
+    }
+
+    #[test]
+    fn wim_e8_translation() {
+        // A call at position 4 to the absolute offset -1, which lies in the translation size.
+        let mut data = [0u8; 20];
+        data[4] = 0xE8;
+        data[5..9].copy_from_slice(&(-1i32).to_le_bytes());
+
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b011, 3);
+        writer.write_bit(false);
+        writer.write_bits(data.len() as u32, 16);
+        writer.align();
+        (0..3).for_each(|_| writer.write_u32_le(1));
+        writer.write_raw(&data);
+
+        let mut lzxd = Lzxd::with_variant(WindowSize::KB32, Variant::Wim);
+        let out = lzxd.decompress_next(&writer.finish(), data.len()).unwrap();
+        assert_eq!(out[5..9], (WIM_E8_TRANSLATION_SIZE - 1).to_le_bytes());
+    }
+
+    // An uncompressed block with E8 translation enabled, containing two calls to fix up.
+    const E8_CHUNK: [u8; 188] = [
+        0x5B, 0x80, 0x80, 0x8D, 0x00, 0x30, 0x80, 0x0A, 0x18, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        0x00, 0x05, 0x00, 0x00, 0x00, 0x54, 0x68, 0x69, 0x73, 0x20, 0x66, 0x69, 0x6C, 0x65, 0x20,
+        0x68, 0x61, 0x73, 0x20, 0x61, 0x6E, 0x20, 0x45, 0x38, 0x20, 0x62, 0x79, 0x74, 0x65, 0x20,
+        0x74, 0x6F, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x45, 0x38, 0x20, 0x74, 0x72, 0x61, 0x6E,
+        0x73, 0x6C, 0x61, 0x74, 0x69, 0x6F, 0x6E, 0x2C, 0x20, 0x58, 0x64, 0x64, 0x64, 0x64, 0x64,
+        0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
+        0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
+        0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
+        0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
+        0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
+        0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
+        0x64, 0xE8, 0x7B, 0x00, 0x00, 0x00, 0xE8, 0x7B, 0x00, 0x00, 0x00, 0x64, 0x64, 0x64, 0x64,
+        0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
+    ];
+
     #[test]
     fn check_e8() {
-        let data = [
-            0x5B, 0x80, 0x80, 0x8D, 0x00, 0x30, 0x80, 0x0A, 0x18, 0x00, 0x00, 0x00, 0x01, 0x00,
-            0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x54, 0x68, 0x69, 0x73, 0x20, 0x66, 0x69, 0x6C,
-            0x65, 0x20, 0x68, 0x61, 0x73, 0x20, 0x61, 0x6E, 0x20, 0x45, 0x38, 0x20, 0x62, 0x79,
-            0x74, 0x65, 0x20, 0x74, 0x6F, 0x20, 0x74, 0x65, 0x73, 0x74, 0x20, 0x45, 0x38, 0x20,
-            0x74, 0x72, 0x61, 0x6E, 0x73, 0x6C, 0x61, 0x74, 0x69, 0x6F, 0x6E, 0x2C, 0x20, 0x58,
-            0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
-            0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
-            0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
-            0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
-            0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
-            0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
-            0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64, 0xE8, 0x7B,
-            0x00, 0x00, 0x00, 0xE8, 0x7B, 0x00, 0x00, 0x00, 0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
-            0x64, 0x64, 0x64, 0x64, 0x64, 0x64,
-        ];
+        let data = E8_CHUNK;
 
         let mut lzxd = Lzxd::new(WindowSize::KB32);
         let res = lzxd.decompress_next(&data, 168);
@@ -449,4 +1039,382 @@ mod tests {
               dddddddddddddd\xE8\xE9\xFF\xFF\xFF\xE8\xE4\xFF\xFF\xFFdddddddddddd"
         );
     }
+
+    #[test]
+    fn check_reference() {
+        // Match of offset 5 and length 5 (position slot 5, one verbatim bit set) followed by
+        // the literal '!'.
+        let mut writer = BitWriter::new();
+        writer.write_verbatim_header(WindowSize::KB32, 6, &[(b'!' as usize, 1), (299, 1)], &[]);
+        writer.write_bits(1, 1);
+        writer.write_bits(1, 1);
+        writer.write_bits(0, 1);
+        let data = writer.finish();
+
+        let mut lzxd = Lzxd::with_reference(WindowSize::KB32, b"hello world").unwrap();
+        let res = lzxd.decompress_next(&data, 6);
+        assert_eq!(res.unwrap(), b"world!");
+    }
+
+    #[test]
+    fn check_reference_full_window() {
+        // Same stream as above, but reaching into the end of a reference filling the window.
+        let mut writer = BitWriter::new();
+        writer.write_verbatim_header(WindowSize::KB32, 6, &[(b'!' as usize, 1), (299, 1)], &[]);
+        writer.write_bits(1, 1);
+        writer.write_bits(1, 1);
+        writer.write_bits(0, 1);
+        let data = writer.finish();
+
+        let mut reference = vec![0; MAX_CHUNK_SIZE];
+        reference[MAX_CHUNK_SIZE - 5..].copy_from_slice(b"abcde");
+        let mut lzxd = Lzxd::with_reference(WindowSize::KB32, &reference).unwrap();
+        let res = lzxd.decompress_next(&data, 6);
+        assert_eq!(res.unwrap(), b"abcde!");
+    }
+
+    #[test]
+    fn check_reference_too_long() {
+        assert_eq!(
+            Lzxd::with_reference(WindowSize::KB32, &[0; MAX_CHUNK_SIZE + 1]).err(),
+            Some(DecodeFailed::WindowTooSmall.into())
+        );
+    }
+
+    /// Writes a literal 'a' followed by a repeated-offset match of length 257 (which takes a
+    /// length footer) and the given bits after it.
+    fn long_match_stream(size: u32, extra: &[(u32, u8)]) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        writer.write_verbatim_header(
+            WindowSize::KB32,
+            size,
+            &[(b'a' as usize, 1), (263, 1)],
+            &[(0, 1), (248, 1)],
+        );
+        writer.write_bits(0, 1);
+        writer.write_bits(1, 1);
+        writer.write_bits(1, 1);
+        extra
+            .iter()
+            .for_each(|&(value, bits)| writer.write_bits(value, bits));
+        writer.finish()
+    }
+
+    fn check_extra_length(extra: &[(u32, u8)], length: usize) {
+        let data = long_match_stream(1 + length as u32, extra);
+        let mut lzxd = Lzxd::with_variant(WindowSize::KB32, Variant::LzxDelta);
+        let res = lzxd.decompress_next(&data, 1 + length).unwrap();
+        assert_eq!(res.len(), 1 + length);
+        assert!(res.iter().all(|&x| x == b'a'));
+    }
+
+    #[test]
+    fn check_extra_length_8_bits() {
+        check_extra_length(&[(0b0, 1), (3, 8)], 257 + 3);
+    }
+
+    #[test]
+    fn check_extra_length_10_bits() {
+        check_extra_length(&[(0b10, 2), (5, 10)], 257 + 256 + 5);
+    }
+
+    #[test]
+    fn check_extra_length_12_bits() {
+        check_extra_length(&[(0b110, 3), (7, 12)], 257 + 1024 + 256 + 7);
+    }
+
+    #[test]
+    fn check_extra_length_15_bits() {
+        check_extra_length(&[(0b111, 3), (20000, 15)], 257 + 20000);
+    }
+
+    #[test]
+    fn check_no_extra_length() {
+        // Classic LZX has no extra length, so the bits after the match are another literal.
+        let data = long_match_stream(259, &[(0b0, 1)]);
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        let res = lzxd.decompress_next(&data, 259).unwrap();
+        assert!(res.iter().all(|&x| x == b'a'));
+    }
+
+    #[test]
+    fn check_e8_into() {
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        let mut out = [0; 168];
+        assert_eq!(lzxd.decompress_next_into(&E8_CHUNK, &mut out), Ok(168));
+        assert_eq!(
+            &out[..],
+            b"This file has an E8 byte to test E8 translation, Xdddddddddddddddd\
+              dddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddd\
+              dddddddddddddd\xE8\xE9\xFF\xFF\xFF\xE8\xE4\xFF\xFF\xFFdddddddddddd"
+        );
+    }
+
+    #[test]
+    fn check_into_consecutive() {
+        // An uncompressed block of 6 bytes split into two chunks of 3 bytes each.
+        let first = [
+            0x00, 0x30, 0x60, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00,
+            0x00, 0x00, b'a', b'b', b'c',
+        ];
+        let second = [b'd', b'e', b'f'];
+
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        let mut out = vec![0; 6];
+        assert_eq!(lzxd.decompress_next_into(&first, &mut out[..3]), Ok(3));
+        assert_eq!(lzxd.decompress_next_into(&second, &mut out[3..]), Ok(3));
+        assert_eq!(out, b"abcdef");
+    }
+
+    #[test]
+    fn check_decompress_all() {
+        // An uncompressed block of 6 bytes split into two chunks of 3 bytes each.
+        let data = [
+            0xFF, 0x00, 0x03, 0x00, 0x13, 0x00, 0x30, 0x60, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, b'a', b'b', b'c', 0xFF, 0x00, 0x03, 0x00,
+            0x03, b'd', b'e', b'f',
+        ];
+
+        let res = decompress_all(&data, Framing::Xnb, WindowSize::KB32, Variant::Lzx, 6);
+        assert_eq!(res.unwrap(), b"abcdef");
+    }
+
+    #[test]
+    fn check_decompress_all_e8() {
+        let mut data = vec![0xBC, 0x00];
+        data.extend(E8_CHUNK);
+
+        let res = decompress_all(&data, Framing::MsPatch, WindowSize::KB32, Variant::Lzx, 168);
+        assert_eq!(
+            res.unwrap(),
+            b"This file has an E8 byte to test E8 translation, Xdddddddddddddddd\
+              dddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddddd\
+              dddddddddddddd\xE8\xE9\xFF\xFF\xFF\xE8\xE4\xFF\xFF\xFFdddddddddddd"
+        );
+    }
+
+    #[test]
+    fn check_decompress_all_invalid_offset() {
+        // A match right at the start, with the initial R0 of 1.
+        let mut writer = BitWriter::new();
+        writer.write_verbatim_header(WindowSize::KB32, 2, &[(0, 1), (256, 1)], &[]);
+        writer.write_bits(1, 1);
+        let chunk = writer.finish();
+
+        let mut data = (chunk.len() as u16).to_le_bytes().to_vec();
+        data.extend(&chunk);
+
+        let err =
+            decompress_all(&data, Framing::MsPatch, WindowSize::KB32, Variant::Lzx, 2).unwrap_err();
+        assert_eq!(err.kind(), DecodeFailed::InvalidMatchOffset(1));
+        assert_eq!(err.chunk_index(), 0);
+        assert_eq!(err.block_index(), 0);
+        assert_eq!(err.output_offset(), 0);
+    }
+
+    #[test]
+    fn check_decompress_all_chunk_too_long() {
+        let data = [0xFF, 0x80, 0x01, 0x00, 0x00];
+        let err = decompress_all(&data, Framing::Xnb, WindowSize::KB32, Variant::Lzx, 10);
+        assert_eq!(err.unwrap_err().kind(), DecodeFailed::ChunkTooLong);
+    }
+
+    #[test]
+    fn error_context() {
+        let data = [
+            0x00, 0x30, 0x60, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00,
+            0x00, 0x00, b'a', b'b', b'c',
+        ];
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        lzxd.decompress_next(&data, 3).unwrap();
+        lzxd.decompress_next(b"def", 3).unwrap();
+
+        // A new block of size 3 with the invalid type 0.
+        let err = lzxd
+            .decompress_next(&[0x00, 0x00, 0x60, 0x00], 3)
+            .unwrap_err();
+        assert_eq!(err.kind(), DecodeFailed::InvalidBlock(0));
+        assert_eq!(err.chunk_index(), 2);
+        assert_eq!(err.byte_offset(), 3);
+        assert_eq!(err.bit_offset(), 3);
+        assert_eq!(err.block_index(), 1);
+        assert_eq!(err.output_offset(), 6);
+        assert_eq!(
+            err.to_string(),
+            "block type 0 is invalid (chunk 2, byte 3, bit 3, block 1, output offset 6)"
+        );
+    }
+
+    #[test]
+    fn error_context_mid_chunk() {
+        // A literal followed by a match reaching past the end of the chunk.
+        let data = long_match_stream(261, &[(0b0, 1), (3, 8)]);
+        let mut lzxd = Lzxd::with_variant(WindowSize::KB32, Variant::LzxDelta);
+        let err = lzxd.decompress_next(&data, 100).unwrap_err();
+        assert_eq!(err.kind(), DecodeFailed::OverreadChunk);
+        assert_eq!(err.chunk_index(), 0);
+        assert_eq!(err.block_index(), 0);
+        assert_eq!(err.output_offset(), 1);
+    }
+
+    #[test]
+    fn error_chunk_too_long() {
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        let err = lzxd.decompress_next(&[], MAX_CHUNK_SIZE + 1).unwrap_err();
+        assert_eq!(err.kind(), DecodeFailed::ChunkTooLong);
+        assert_eq!(err.chunk_index(), 0);
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        BlockStart(BlockType, u32),
+        Literal(u8),
+        Match(usize, usize, Option<u8>),
+        Uncompressed(usize),
+        E8Fixup(usize),
+    }
+
+    #[derive(Default)]
+    struct Recorder(Vec<Event>);
+
+    impl Visitor for Recorder {
+        fn on_block_start(&mut self, block: &BlockInfo<'_>) {
+            assert_eq!(block.size(), block.remaining());
+            self.0
+                .push(Event::BlockStart(block.block_type(), block.size()));
+        }
+
+        fn on_literal(&mut self, value: u8) {
+            self.0.push(Event::Literal(value));
+        }
+
+        fn on_match(&mut self, offset: usize, length: usize, repeated_slot: Option<u8>) {
+            self.0.push(Event::Match(offset, length, repeated_slot));
+        }
+
+        fn on_uncompressed(&mut self, len: usize) {
+            self.0.push(Event::Uncompressed(len));
+        }
+
+        fn on_e8_fixup(&mut self, pos: usize) {
+            self.0.push(Event::E8Fixup(pos));
+        }
+    }
+
+    #[test]
+    fn visit_repeated_match() {
+        let data = long_match_stream(261, &[(0b0, 1), (3, 8)]);
+        let mut lzxd = Lzxd::with_variant(WindowSize::KB32, Variant::LzxDelta);
+        let mut recorder = Recorder::default();
+        lzxd.decompress_next_with_visitor(&data, 261, &mut recorder)
+            .unwrap();
+
+        assert_eq!(
+            recorder.0,
+            [
+                Event::BlockStart(BlockType::Verbatim, 261),
+                Event::Literal(b'a'),
+                Event::Match(1, 260, Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn visit_reference_match() {
+        let mut writer = BitWriter::new();
+        writer.write_verbatim_header(WindowSize::KB32, 6, &[(b'!' as usize, 1), (299, 1)], &[]);
+        writer.write_bits(1, 1);
+        writer.write_bits(1, 1);
+        writer.write_bits(0, 1);
+        let data = writer.finish();
+
+        let mut lzxd = Lzxd::with_reference(WindowSize::KB32, b"hello world").unwrap();
+        let mut recorder = Recorder::default();
+        lzxd.decompress_next_with_visitor(&data, 6, &mut recorder)
+            .unwrap();
+
+        assert_eq!(
+            recorder.0,
+            [
+                Event::BlockStart(BlockType::Verbatim, 6),
+                Event::Match(5, 5, None),
+                Event::Literal(b'!'),
+            ]
+        );
+    }
+
+    #[test]
+    fn visit_e8_fixups() {
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        let mut recorder = Recorder::default();
+        lzxd.decompress_next_with_visitor(&E8_CHUNK, 168, &mut recorder)
+            .unwrap();
+
+        assert_eq!(
+            recorder.0,
+            [
+                Event::BlockStart(BlockType::Uncompressed, 168),
+                Event::Uncompressed(168),
+                Event::E8Fixup(146),
+                Event::E8Fixup(151),
+            ]
+        );
+    }
+
+    #[test]
+    fn current_block_verbatim() {
+        let data = long_match_stream(261, &[(0b0, 1), (3, 8)]);
+        let mut lzxd = Lzxd::with_variant(WindowSize::KB32, Variant::LzxDelta);
+        assert!(lzxd.current_block().is_none());
+
+        // Only decompress part of the block, so that it's still current afterwards.
+        lzxd.decompress_next(&data, 1).unwrap();
+        let block = lzxd.current_block().unwrap();
+        assert_eq!(block.block_type(), BlockType::Verbatim);
+        assert_eq!(block.size(), 261);
+        assert_eq!(block.remaining(), 260);
+        assert_eq!(block.repeated_offsets(), None);
+
+        let trees = block.trees();
+        assert_eq!(trees.aligned_offset(), None);
+        let main = trees.main().unwrap();
+        assert_eq!(main.len(), 256 + 8 * WindowSize::KB32.position_slots());
+        assert!(main
+            .iter()
+            .enumerate()
+            .all(|(i, &len)| len == u8::from(i == b'a' as usize || i == 263)));
+        let length = trees.length().unwrap();
+        assert_eq!(length.len(), 249);
+        assert!(length
+            .iter()
+            .enumerate()
+            .all(|(i, &len)| len == u8::from(i == 0 || i == 248)));
+    }
+
+    #[test]
+    fn current_block_uncompressed() {
+        let data = [
+            0x00, 0x30, 0x60, 0x00, 0x05, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x07, 0x00,
+            0x00, 0x00, b'a', b'b', b'c',
+        ];
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        lzxd.decompress_next(&data, 3).unwrap();
+
+        let block = lzxd.current_block().unwrap();
+        assert_eq!(block.block_type(), BlockType::Uncompressed);
+        assert_eq!(block.size(), 6);
+        assert_eq!(block.remaining(), 3);
+        assert_eq!(block.repeated_offsets(), Some([5, 6, 7]));
+        assert_eq!(
+            block.trees(),
+            BlockTrees {
+                aligned_offset: None,
+                main: None,
+                length: None,
+            }
+        );
+
+        lzxd.decompress_next(b"def", 3).unwrap();
+        assert!(lzxd.current_block().is_none());
+    }
 }
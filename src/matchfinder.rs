@@ -0,0 +1,374 @@
+//! Match finders, used by the encoder to look up previous occurrences of the data.
+//!
+//! Positions are indices into the history buffer of the encoder, which holds the data that
+//! matches can still reach followed by the chunk being compressed. Every position of the data
+//! must be given to the match finder in order, either to find matches for it or to skip it.
+use alloc::{vec, vec::Vec};
+
+/// The shortest match found by looking up previous occurrences of the data.
+pub const MIN_MATCH: usize = 3;
+
+/// How many bits of the next bytes are used to look up previous occurrences.
+const HASH_BITS: u32 = 16;
+
+/// Marks the absence of a position.
+const NIL: u32 = u32::MAX;
+
+/// A previous occurrence of the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub length: u32,
+    pub offset: u32,
+}
+
+/// The limits matches must respect at a given position.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// The longest match to look for, which must not be past the end of the data.
+    pub max_len: usize,
+
+    /// The farthest back a match can reach.
+    pub max_offset: usize,
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], 0]);
+    (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// How many bytes starting at `a` and `b` are the same, starting after the first `len` known
+/// to match, up to `max_len`.
+fn extend_match(data: &[u8], a: usize, b: usize, len: usize, max_len: usize) -> usize {
+    len + data[a + len..a + max_len]
+        .iter()
+        .zip(&data[b + len..])
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+/// Moves the positions back by `amount`, forgetting those that would fall before the start.
+fn slide_positions(positions: &mut [u32], amount: usize) {
+    positions
+        .iter_mut()
+        .filter(|pos| **pos != NIL)
+        .for_each(|pos| {
+            *pos = (*pos as usize)
+                .checked_sub(amount)
+                .map_or(NIL, |p| p as u32)
+        });
+}
+
+/// Finds matches by walking a chain with every previous position whose next bytes hashed the
+/// same, from the most recent to the oldest one.
+pub struct HashChain {
+    head: Vec<u32>,
+    prev: Vec<u32>,
+    /// How many positions of the chain are checked at most.
+    max_chain: usize,
+    /// A match this long is good enough to stop looking for longer ones.
+    nice_len: usize,
+}
+
+impl HashChain {
+    pub fn new(max_chain: usize, nice_len: usize) -> Self {
+        Self {
+            head: vec![NIL; 1 << HASH_BITS],
+            prev: Vec::new(),
+            max_chain,
+            nice_len,
+        }
+    }
+
+    /// Inserts the position into its chain, returning the previous head of the chain.
+    fn insert(&mut self, data: &[u8], pos: usize) -> u32 {
+        self.prev.resize(pos + 1, NIL);
+        if pos + MIN_MATCH > data.len() {
+            return NIL;
+        }
+
+        let hash = hash(data, pos);
+        let cur = self.head[hash];
+        self.prev[pos] = cur;
+        self.head[hash] = pos as u32;
+        cur
+    }
+
+    fn find_matches(&mut self, data: &[u8], pos: usize, limits: Limits, out: &mut Vec<Match>) {
+        let mut cur = self.insert(data, pos);
+        let nice_len = self.nice_len.min(limits.max_len);
+
+        let mut best_len = MIN_MATCH - 1;
+        for _ in 0..self.max_chain {
+            if cur == NIL || pos - cur as usize > limits.max_offset || best_len >= nice_len {
+                break;
+            }
+
+            let candidate = cur as usize;
+            // Only compare everything if it can be longer than the best match.
+            if data[candidate + best_len] == data[pos + best_len] {
+                let len = extend_match(data, pos, candidate, 0, limits.max_len);
+                if len > best_len {
+                    best_len = len;
+                    out.push(Match {
+                        length: len as u32,
+                        offset: (pos - candidate) as u32,
+                    });
+                }
+            }
+            cur = self.prev[candidate];
+        }
+    }
+
+    fn slide(&mut self, amount: usize) {
+        slide_positions(&mut self.head, amount);
+        self.prev.drain(..amount.min(self.prev.len()));
+        slide_positions(&mut self.prev, amount);
+    }
+}
+
+/// Finds matches by keeping every previous position whose next bytes hashed the same in a
+/// binary search tree, ordered by the data following them, and rebuilt with the most recent
+/// position at the root every time one is inserted.
+///
+/// It's slower than a hash chain to find a few matches, but it checks much fewer positions
+/// to find the longest ones.
+pub struct BinaryTree {
+    head: Vec<u32>,
+    /// The two children of every position, the lesser first and the greater second.
+    children: Vec<u32>,
+    /// How many positions of the tree are checked at most.
+    max_depth: usize,
+    /// A match this long is good enough to stop looking for longer ones.
+    nice_len: usize,
+}
+
+impl BinaryTree {
+    pub fn new(max_depth: usize, nice_len: usize) -> Self {
+        Self {
+            head: vec![NIL; 1 << HASH_BITS],
+            children: Vec::new(),
+            max_depth,
+            nice_len,
+        }
+    }
+
+    /// Inserts the position into its tree, finding the matches on the way if `out` is given.
+    fn insert(
+        &mut self,
+        data: &[u8],
+        pos: usize,
+        limits: Limits,
+        mut out: Option<&mut Vec<Match>>,
+    ) {
+        self.children.resize(2 * (pos + 1), NIL);
+        if pos + MIN_MATCH > data.len() {
+            return;
+        }
+
+        let hash = hash(data, pos);
+        let mut cur = self.head[hash];
+        self.head[hash] = pos as u32;
+
+        // The children slots where the next lesser and greater positions go.
+        let mut lesser_slot = 2 * pos;
+        let mut greater_slot = 2 * pos + 1;
+
+        let max_len = self.nice_len.min(limits.max_len);
+        let mut best_len = MIN_MATCH - 1;
+        for _ in 0..self.max_depth {
+            if cur == NIL || pos - cur as usize > limits.max_offset {
+                break;
+            }
+
+            let candidate = cur as usize;
+            // The tree is only ordered up to the end of the chunk being compressed when the
+            // positions near it were inserted, so the bytes are always compared from the start
+            // rather than trusting how many are known to be in common already.
+            let len = extend_match(data, pos, candidate, 0, max_len);
+            if len > best_len {
+                best_len = len;
+                if let Some(out) = out.as_mut() {
+                    // The tree only orders positions up to the nice length, but the match
+                    // itself may go on for longer.
+                    let length = if len == max_len {
+                        extend_match(data, pos, candidate, len, limits.max_len)
+                    } else {
+                        len
+                    };
+                    out.push(Match {
+                        length: length as u32,
+                        offset: (pos - candidate) as u32,
+                    });
+                }
+            }
+
+            if len == max_len {
+                // The candidate can't be told apart from the current data, so the current
+                // data takes its place in the tree.
+                self.children[lesser_slot] = self.children[2 * candidate];
+                self.children[greater_slot] = self.children[2 * candidate + 1];
+                return;
+            }
+
+            if data[candidate + len] < data[pos + len] {
+                // The candidate and its lesser children go to the lesser side, and its greater
+                // children still need to be sorted out.
+                self.children[lesser_slot] = cur;
+                lesser_slot = 2 * candidate + 1;
+                cur = self.children[lesser_slot];
+            } else {
+                self.children[greater_slot] = cur;
+                greater_slot = 2 * candidate;
+                cur = self.children[greater_slot];
+            }
+        }
+
+        self.children[lesser_slot] = NIL;
+        self.children[greater_slot] = NIL;
+    }
+
+    fn slide(&mut self, amount: usize) {
+        slide_positions(&mut self.head, amount);
+        self.children.drain(..(2 * amount).min(self.children.len()));
+        slide_positions(&mut self.children, amount);
+    }
+}
+
+/// The match finders the encoder can use.
+pub enum MatchFinder {
+    HashChain(HashChain),
+    BinaryTree(BinaryTree),
+}
+
+impl MatchFinder {
+    /// Finds the matches for the data at `pos`, which are added to `out` from shortest to
+    /// longest, and remembers the position for the next ones.
+    pub fn find_matches(&mut self, data: &[u8], pos: usize, limits: Limits, out: &mut Vec<Match>) {
+        match self {
+            Self::HashChain(finder) => finder.find_matches(data, pos, limits, out),
+            Self::BinaryTree(finder) => finder.insert(data, pos, limits, Some(out)),
+        }
+    }
+
+    /// Remembers the position for the next matches without looking for its matches.
+    pub fn skip(&mut self, data: &[u8], pos: usize, limits: Limits) {
+        match self {
+            Self::HashChain(finder) => {
+                finder.insert(data, pos);
+            }
+            Self::BinaryTree(finder) => finder.insert(data, pos, limits, None),
+        }
+    }
+
+    /// Moves every position back by `amount`, after the start of the data is discarded.
+    pub fn slide(&mut self, amount: usize) {
+        match self {
+            Self::HashChain(finder) => finder.slide(amount),
+            Self::BinaryTree(finder) => finder.slide(amount),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        max_len: 257,
+        max_offset: 1 << 20,
+    };
+
+    fn finders() -> [MatchFinder; 2] {
+        [
+            MatchFinder::HashChain(HashChain::new(64, 257)),
+            MatchFinder::BinaryTree(BinaryTree::new(64, 257)),
+        ]
+    }
+
+    /// The default limits, with matches not going past the end of the data.
+    fn limits_at(data: &[u8], pos: usize) -> Limits {
+        Limits {
+            max_len: LIMITS.max_len.min(data.len() - pos),
+            ..LIMITS
+        }
+    }
+
+    /// Finds the matches at every position of the data.
+    fn all_matches(finder: &mut MatchFinder, data: &[u8]) -> Vec<Vec<Match>> {
+        (0..data.len())
+            .map(|pos| {
+                let mut out = Vec::new();
+                finder.find_matches(data, pos, limits_at(data, pos), &mut out);
+                out
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find_longest_match() {
+        let data = b"abcdefgh abcdxyz abcdefgh";
+        for mut finder in finders() {
+            let matches = all_matches(&mut finder, data);
+            assert_eq!(
+                matches[17].last(),
+                Some(&Match {
+                    length: 8,
+                    offset: 17
+                })
+            );
+            assert_eq!(
+                matches[9].last(),
+                Some(&Match {
+                    length: 4,
+                    offset: 9
+                })
+            );
+            assert!(matches[..9].iter().all(|m| m.is_empty()));
+        }
+    }
+
+    #[test]
+    fn matches_are_increasing() {
+        let data = b"abcd abcde abcdef abcdefg abcdefgh";
+        for mut finder in finders() {
+            let matches = all_matches(&mut finder, data);
+            let last = &matches[26];
+            assert_eq!(last.last().map(|m| m.length), Some(7));
+            assert!(last.windows(2).all(|w| w[0].length < w[1].length));
+            assert!(last
+                .iter()
+                .all(|m| data[26 - m.offset as usize..]
+                    .starts_with(&data[26..26 + m.length as usize])));
+        }
+    }
+
+    #[test]
+    fn respect_limits() {
+        let data = b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa";
+        for mut finder in finders() {
+            let mut out = Vec::new();
+            (0..40).for_each(|pos| finder.skip(data, pos, limits_at(data, pos)));
+            let limits = Limits {
+                max_len: 10,
+                max_offset: 5,
+            };
+            finder.find_matches(data, 40, limits, &mut out);
+            assert!(!out.is_empty());
+            assert!(out.iter().all(|m| m.length <= 10 && m.offset <= 5));
+        }
+    }
+
+    #[test]
+    fn slide_forgets_old_positions() {
+        let data = b"abcdefgh12345678abcdefgh";
+        for mut finder in finders() {
+            (0..16).for_each(|pos| finder.skip(data, pos, limits_at(data, pos)));
+            finder.slide(8);
+
+            let data = &data[8..];
+            let mut out = Vec::new();
+            finder.find_matches(data, 8, limits_at(data, 8), &mut out);
+            assert!(out.is_empty());
+        }
+    }
+}
@@ -0,0 +1,412 @@
+//! Decompression and patching of Exchange Offline Address Book (OAB) files.
+//!
+//! OAB files are downloaded by Outlook either in full or as differential patches against the
+//! previous version, both of them as `.lzx` files. The data is split into blocks, each of
+//! which is compressed on its own as an LZX DELTA stream, with its chunks delimited as
+//! described in [`Framing::MsPatch`] and followed by a CRC of the block once decompressed.
+//! The blocks of a patch are matched against consecutive parts of the previous version.
+//!
+//! The CRCs are assumed to be the standard CRC-32, as used by zlib and PNG.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use ::lzxd::oab;
+//!
+//! let full = oab::decompress(&std::fs::read("udetails.lzx")?)?;
+//! let patched = oab::apply_patch(&full, &std::fs::read("udetails-patch.lzx")?)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Framing::MsPatch`]: ../enum.Framing.html#variant.MsPatch
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::bytes::ByteReader;
+use crate::{DecompressError, Lzxd, Variant, WindowSize, MAX_CHUNK_SIZE};
+
+/// The version at the start of full OAB files.
+const FULL_VERSION: (u32, u32) = (3, 1);
+
+/// The version at the start of OAB patch files.
+const PATCH_VERSION: (u32, u32) = (3, 2);
+
+/// The block of a full file is stored as-is.
+const BLOCK_UNCOMPRESSED: u32 = 0;
+
+/// The block of a full file is LZX compressed.
+const BLOCK_COMPRESSED: u32 = 1;
+
+/// Lookup table for the CRC-32 of every byte value.
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The error type used when an OAB file cannot be decompressed or a patch applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OabError {
+    /// The data does not start with the version of a full OAB file or patch, whichever was
+    /// expected.
+    InvalidVersion,
+
+    /// The data ended before the file was fully read.
+    UnexpectedEof,
+
+    /// The file contains a value that doesn't make sense, such as a block larger than the
+    /// maximum block size.
+    InvalidData,
+
+    /// The base OAB file is not the one the patch was made against, as its size or CRC differ.
+    BaseMismatch,
+
+    /// The CRC of a block or of the patched file is not the expected one.
+    CrcMismatch,
+
+    /// The LZX compressed data of a block could not be decompressed.
+    Decompress(DecompressError),
+}
+
+impl fmt::Display for OabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use OabError::*;
+
+        match self {
+            InvalidVersion => write!(f, "data is not an OAB file of the expected version"),
+            UnexpectedEof => write!(f, "reached end of data without fully reading the OAB file"),
+            InvalidData => write!(f, "OAB file contains invalid data"),
+            BaseMismatch => write!(f, "patch does not apply to the base OAB file"),
+            CrcMismatch => write!(f, "CRC of the decompressed data does not match"),
+            Decompress(e) => write!(f, "failed to decompress OAB block: {}", e),
+        }
+    }
+}
+
+impl core::error::Error for OabError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Decompress(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecompressError> for OabError {
+    fn from(e: DecompressError) -> Self {
+        Self::Decompress(e)
+    }
+}
+
+/// Computes the CRC-32 of the data.
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ crc >> 8
+    })
+}
+
+/// The smallest window of 128 KB to 32 MB that holds `len` bytes, or the largest one.
+fn window_size(len: usize) -> WindowSize {
+    let bits = (17..25).find(|bits| 1 << bits >= len).unwrap_or(25);
+    WindowSize::from_value(1 << bits).unwrap()
+}
+
+/// Reads the version at the start of the file, checking that it's the expected one.
+fn read_version(reader: &mut ByteReader<OabError>, expected: (u32, u32)) -> Result<(), OabError> {
+    if (reader.read_u32()?, reader.read_u32()?) == expected {
+        Ok(())
+    } else {
+        Err(OabError::InvalidVersion)
+    }
+}
+
+/// Decompresses a block of `len` bytes into the output, and checks its CRC.
+fn decompress_block(
+    mut lzxd: Lzxd,
+    data: &[u8],
+    len: usize,
+    crc: u32,
+    output: &mut Vec<u8>,
+) -> Result<(), OabError> {
+    let start = output.len();
+    let mut reader = ByteReader::new(data, OabError::UnexpectedEof);
+    while output.len() - start < len {
+        let chunk_len = reader.read_u16()? as usize;
+        let chunk = reader.read(chunk_len)?;
+        let output_len = (len - (output.len() - start)).min(MAX_CHUNK_SIZE);
+        output.extend_from_slice(lzxd.decompress_next(chunk, output_len)?);
+    }
+
+    if crc32(&output[start..]) == crc {
+        Ok(())
+    } else {
+        Err(OabError::CrcMismatch)
+    }
+}
+
+/// Decompresses a full OAB `.lzx` file.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, OabError> {
+    let mut reader = ByteReader::new(data, OabError::UnexpectedEof);
+    read_version(&mut reader, FULL_VERSION)?;
+    let block_max = reader.read_u32()? as usize;
+    let target_size = reader.read_u32()? as usize;
+    let window_size = window_size(block_max);
+
+    // The target size can't be trusted until the blocks are read, so nothing is reserved.
+    let mut output = Vec::new();
+    while output.len() < target_size {
+        let flags = reader.read_u32()?;
+        let compressed_size = reader.read_u32()? as usize;
+        let size = reader.read_u32()? as usize;
+        let crc = reader.read_u32()?;
+        if size == 0 || size > block_max || size > target_size - output.len() {
+            return Err(OabError::InvalidData);
+        }
+
+        let block = reader.read(compressed_size)?;
+        match flags {
+            BLOCK_UNCOMPRESSED if compressed_size == size => {
+                if crc32(block) != crc {
+                    return Err(OabError::CrcMismatch);
+                }
+                output.extend_from_slice(block);
+            }
+            BLOCK_COMPRESSED => {
+                let lzxd = Lzxd::with_variant(window_size, Variant::LzxDelta);
+                decompress_block(lzxd, block, size, crc, &mut output)?;
+            }
+            _ => return Err(OabError::InvalidData),
+        }
+    }
+
+    Ok(output)
+}
+
+/// Applies an OAB `.lzx` patch file to the `base` OAB file it was made against, returning the
+/// patched OAB file.
+pub fn apply_patch(base: &[u8], patch: &[u8]) -> Result<Vec<u8>, OabError> {
+    let mut reader = ByteReader::new(patch, OabError::UnexpectedEof);
+    read_version(&mut reader, PATCH_VERSION)?;
+    let block_max = reader.read_u32()? as usize;
+    let source_size = reader.read_u32()? as usize;
+    let target_size = reader.read_u32()? as usize;
+    let source_crc = reader.read_u32()?;
+    let target_crc = reader.read_u32()?;
+    if base.len() != source_size || crc32(base) != source_crc {
+        return Err(OabError::BaseMismatch);
+    }
+
+    let mut source = base;
+    let mut output = Vec::new();
+    while output.len() < target_size {
+        let patch_size = reader.read_u32()? as usize;
+        let size = reader.read_u32()? as usize;
+        let reference_size = reader.read_u32()? as usize;
+        let crc = reader.read_u32()?;
+        if size == 0 || size > block_max || size > target_size - output.len() {
+            return Err(OabError::InvalidData);
+        }
+
+        // Every block is matched against the part of the base that follows the previous one,
+        // with a window large enough to hold both.
+        if reference_size > source.len() {
+            return Err(OabError::InvalidData);
+        }
+        let (reference, rest) = source.split_at(reference_size);
+        source = rest;
+        let window_size = window_size(reference_size.next_multiple_of(MAX_CHUNK_SIZE) + size);
+        let lzxd = Lzxd::with_reference(window_size, reference)?;
+        decompress_block(lzxd, reader.read(patch_size)?, size, crc, &mut output)?;
+    }
+
+    if crc32(&output) == target_crc {
+        Ok(output)
+    } else {
+        Err(OabError::CrcMismatch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{CompressionLevel, LzxdEncoder};
+
+    const BLOCK_MAX: usize = 3 * MAX_CHUNK_SIZE;
+
+    /// Compresses a block with the encoder, delimiting its chunks with their size.
+    fn compress_block(mut encoder: LzxdEncoder, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for chunk in encoder.compress_chunks(data) {
+            out.extend((chunk.len() as u16).to_le_bytes());
+            out.extend(chunk);
+        }
+        out
+    }
+
+    /// Builds a full OAB file, storing the blocks at the given indices as-is.
+    fn build_full(data: &[u8], stored: &[usize]) -> Vec<u8> {
+        let mut out = Vec::new();
+        [3, 1, BLOCK_MAX as u32, data.len() as u32]
+            .iter()
+            .for_each(|value| out.extend(value.to_le_bytes()));
+        for (i, block) in data.chunks(BLOCK_MAX).enumerate() {
+            let (flags, body) = if stored.contains(&i) {
+                (BLOCK_UNCOMPRESSED, block.to_vec())
+            } else {
+                let encoder = LzxdEncoder::with_variant(window_size(BLOCK_MAX), Variant::LzxDelta);
+                (BLOCK_COMPRESSED, compress_block(encoder, block))
+            };
+            [flags, body.len() as u32, block.len() as u32, crc32(block)]
+                .iter()
+                .for_each(|value| out.extend(value.to_le_bytes()));
+            out.extend(body);
+        }
+        out
+    }
+
+    /// Builds a patch from `base` to `target`, with blocks made of the given `(source, target)`
+    /// sizes.
+    fn build_patch(base: &[u8], target: &[u8], blocks: &[(usize, usize)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        [
+            3,
+            2,
+            BLOCK_MAX as u32,
+            base.len() as u32,
+            target.len() as u32,
+            crc32(base),
+            crc32(target),
+        ]
+        .iter()
+        .for_each(|value| out.extend(value.to_le_bytes()));
+
+        let (mut source, mut target) = (base, target);
+        for &(source_size, target_size) in blocks {
+            let (reference, block) = (&source[..source_size], &target[..target_size]);
+            source = &source[source_size..];
+            target = &target[target_size..];
+
+            let window_size =
+                window_size(source_size.next_multiple_of(MAX_CHUNK_SIZE) + target_size);
+            let encoder =
+                LzxdEncoder::with_reference(window_size, CompressionLevel::default(), reference)
+                    .unwrap();
+            let body = compress_block(encoder, block);
+            [
+                body.len() as u32,
+                target_size as u32,
+                source_size as u32,
+                crc32(block),
+            ]
+            .iter()
+            .for_each(|value| out.extend(value.to_le_bytes()));
+            out.extend(body);
+        }
+        out
+    }
+
+    fn content(len: usize, seed: u32) -> Vec<u8> {
+        let mut seed = seed;
+        let mut out = Vec::with_capacity(len);
+        while out.len() < len {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            out.extend(format!("entry {} <{}@example.com>\n", seed >> 20, seed >> 24).bytes());
+        }
+        out.truncate(len);
+        out
+    }
+
+    #[test]
+    fn check_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn window_sizes() {
+        assert_eq!(window_size(0), WindowSize::KB128);
+        assert_eq!(window_size(0x2_0000), WindowSize::KB128);
+        assert_eq!(window_size(0x2_0001), WindowSize::KB256);
+        assert_eq!(window_size(usize::MAX), WindowSize::MB32);
+    }
+
+    #[test]
+    fn decompress_full() {
+        let data = content(3 * BLOCK_MAX + 1000, 1);
+        let full = build_full(&data, &[1]);
+        assert!(full.len() < data.len());
+        assert_eq!(decompress(&full).unwrap(), data);
+    }
+
+    #[test]
+    fn apply_patches() {
+        let base = content(2 * BLOCK_MAX, 2);
+        let mut target = base.clone();
+        target.splice(1000..1000, content(500, 3));
+        target.drain(BLOCK_MAX + 5000..BLOCK_MAX + 9000);
+        target.extend(content(20_000, 4));
+
+        // The first block of the target is longer than the part of the base it came from.
+        let blocks = [
+            (BLOCK_MAX - 500, BLOCK_MAX),
+            (BLOCK_MAX, BLOCK_MAX),
+            (500, target.len() - 2 * BLOCK_MAX),
+        ];
+        let patch = build_patch(&base, &target, &blocks);
+        assert!(patch.len() < target.len() / 10);
+        assert_eq!(apply_patch(&base, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn errors() {
+        let data = content(BLOCK_MAX + 1000, 5);
+        let full = build_full(&data, &[]);
+        assert_eq!(apply_patch(&[], &full), Err(OabError::InvalidVersion));
+        assert_eq!(
+            decompress(&full[..full.len() - 1]),
+            Err(OabError::UnexpectedEof)
+        );
+
+        // The CRC of the first block.
+        let mut corrupt = full.clone();
+        corrupt[28] ^= 1;
+        assert_eq!(decompress(&corrupt), Err(OabError::CrcMismatch));
+
+        // A block larger than the maximum block size.
+        let mut corrupt = full.clone();
+        corrupt[8..12].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(decompress(&corrupt), Err(OabError::InvalidData));
+
+        // A target size that the blocks don't reach.
+        let mut truncated = full[..16].to_vec();
+        truncated[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(decompress(&truncated), Err(OabError::UnexpectedEof));
+
+        let target = content(1000, 6);
+        let patch = build_patch(&data, &target, &[(data.len(), target.len())]);
+        assert_eq!(decompress(&patch), Err(OabError::InvalidVersion));
+        assert_eq!(apply_patch(&data[1..], &patch), Err(OabError::BaseMismatch));
+        let mut base = data.clone();
+        base[0] ^= 1;
+        assert_eq!(apply_patch(&base, &patch), Err(OabError::BaseMismatch));
+        assert_eq!(apply_patch(&data, &patch).unwrap(), target);
+
+        let mut truncated = patch[..28].to_vec();
+        truncated[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(apply_patch(&data, &truncated), Err(OabError::UnexpectedEof));
+    }
+}
@@ -0,0 +1,401 @@
+//! Near-optimal parsing, used by the encoder at the highest compression levels.
+//!
+//! Rather than taking the longest match available at every position, the parser finds the
+//! cheapest way to encode the whole chunk, pricing every literal and match with the path
+//! lengths the Huffman trees would give them. Those depend on the parse itself, so the parser
+//! runs a few times, each one priced with the trees built from the previous one.
+//!
+//! The cost of a match depends on the repeated offsets at its position, and so on the
+//! matches before it. Every position only remembers the repeated offsets along the cheapest
+//! path found to reach it, which is why this is near-optimal rather than optimal.
+use alloc::{vec, vec::Vec};
+
+use crate::block::{BASE_POSITION, FOOTER_BITS};
+use crate::encoder::{position_slot, Token, MAX_MATCH, MAX_PATH_LENGTH};
+use crate::huffman;
+use crate::matchfinder::Match;
+
+/// The cost of elements not seen in the previous parse, which would have to be added to the
+/// trees. It's high enough to favour the elements that are already in them.
+const UNUSED_COST: u32 = MAX_PATH_LENGTH as u32;
+
+/// The matches found at every position of a chunk, collected once and shared by every pass of
+/// the parser.
+pub struct MatchTable {
+    matches: Vec<Match>,
+    /// Where the matches of every position end in `matches`.
+    ends: Vec<usize>,
+}
+
+impl MatchTable {
+    pub fn new() -> Self {
+        Self {
+            matches: Vec::new(),
+            ends: Vec::new(),
+        }
+    }
+
+    /// Adds the matches of the next position, from shortest to longest.
+    pub fn push(&mut self, matches: &[Match]) {
+        self.matches.extend_from_slice(matches);
+        self.ends.push(self.matches.len());
+    }
+
+    fn get(&self, index: usize) -> &[Match] {
+        let start = index.checked_sub(1).map_or(0, |prev| self.ends[prev]);
+        &self.matches[start..self.ends[index]]
+    }
+}
+
+/// The cost in bits of every element of the main and length trees.
+pub struct Costs {
+    main: Vec<u32>,
+    length: Vec<u32>,
+}
+
+impl Costs {
+    /// Rough costs to use before anything is known about the data.
+    pub fn initial(main_elements: usize, length_elements: usize) -> Self {
+        let mut main = vec![10; main_elements];
+        main[..256].fill(8);
+        Self {
+            main,
+            length: vec![7; length_elements],
+        }
+    }
+
+    /// The costs of the elements in the trees built for the given tokens.
+    pub fn from_tokens(tokens: &[Token], main_elements: usize, length_elements: usize) -> Self {
+        let mut main_freqs = vec![0; main_elements];
+        let mut length_freqs = vec![0; length_elements];
+        tokens.iter().for_each(|&token| {
+            let (main_element, length_element) = token.elements();
+            main_freqs[main_element] += 1;
+            if let Some(length_element) = length_element {
+                length_freqs[length_element] += 1;
+            }
+        });
+
+        let costs = |freqs: &[u32]| {
+            if freqs.iter().all(|&f| f == 0) {
+                return vec![UNUSED_COST; freqs.len()];
+            }
+            huffman::path_lengths(freqs, MAX_PATH_LENGTH)
+                .into_iter()
+                .map(|len| if len == 0 { UNUSED_COST } else { len as u32 })
+                .collect()
+        };
+
+        Self {
+            main: costs(&main_freqs),
+            length: costs(&length_freqs),
+        }
+    }
+
+    fn literal(&self, value: u8) -> u32 {
+        self.main[value as usize]
+    }
+
+    /// The cost of a token, which for matches of LZX DELTA includes the Extra Length field.
+    fn token(&self, token: Token, delta: bool) -> u32 {
+        match token {
+            Token::Literal(value) => self.literal(value),
+            Token::Match {
+                length,
+                position_slot,
+                ..
+            } => self.matched(length as usize, position_slot, delta),
+        }
+    }
+
+    fn matched(&self, length: usize, position_slot: u16, delta: bool) -> u32 {
+        let length_header = (length - 2).min(7);
+        let mut cost = self.main[256 + 8 * position_slot as usize + length_header];
+        if length_header == 7 {
+            cost += self.length[(length - 9).min(248)];
+        }
+        cost += FOOTER_BITS[position_slot as usize] as u32;
+        if delta && length >= MAX_MATCH {
+            cost += match length - MAX_MATCH {
+                0..=255 => 1 + 8,
+                256..=1279 => 2 + 10,
+                1280..=5375 => 3 + 12,
+                _ => 3 + 15,
+            };
+        }
+        cost
+    }
+}
+
+/// What the parser needs to know about the chunk.
+pub struct Input<'a> {
+    /// The history of the encoder, with the chunk at its end.
+    pub history: &'a [u8],
+
+    /// Where the chunk starts in the history.
+    pub start: usize,
+
+    /// The longest match allowed.
+    pub max_match: usize,
+
+    /// The farthest back a match can reach.
+    pub max_offset: usize,
+
+    /// A match this long is good enough to take without considering others.
+    pub nice_len: usize,
+
+    /// Whether matches longer than `MAX_MATCH` need an Extra Length field.
+    pub delta: bool,
+}
+
+impl Input<'_> {
+    /// How many bytes starting at `pos` match those `offset` bytes before it.
+    fn match_len(&self, pos: usize, offset: usize, max_len: usize) -> usize {
+        self.history[pos..pos + max_len]
+            .iter()
+            .zip(&self.history[pos - offset..])
+            .take_while(|(a, b)| a == b)
+            .count()
+    }
+}
+
+/// The cheapest way found to reach some position of the chunk.
+#[derive(Clone, Copy)]
+struct Node {
+    cost: u32,
+    /// The last token of the path, and how many bytes it covers.
+    token: Token,
+    len: u32,
+    /// The repeated offsets after the path.
+    r: [u32; 3],
+}
+
+/// The token of a match at the offset in the given repeated offset slot.
+fn repeated_token(length: usize, slot: usize) -> Token {
+    Token::Match {
+        length: length as u32,
+        position_slot: slot as u16,
+        footer: 0,
+    }
+}
+
+/// The token of a match at an offset that is not a repeated one.
+fn match_token(length: usize, offset: u32) -> Token {
+    let formatted_offset = offset + 2;
+    let position_slot = position_slot(formatted_offset);
+    Token::Match {
+        length: length as u32,
+        position_slot,
+        footer: formatted_offset - BASE_POSITION[position_slot as usize],
+    }
+}
+
+/// The repeated offsets after a match at an offset that is not a repeated one.
+fn push(r: [u32; 3], offset: u32) -> [u32; 3] {
+    [offset, r[0], r[1]]
+}
+
+/// The lengths a match of up to `max_len` bytes is tried with: every one up to `MAX_MATCH`,
+/// but only the full length past that, where they're rare and shortening them is pointless.
+fn lengths(min_len: usize, max_len: usize) -> impl Iterator<Item = usize> {
+    let long = (max_len > MAX_MATCH).then_some(max_len);
+    (min_len..=max_len.min(MAX_MATCH)).chain(long)
+}
+
+/// Finds the cheapest tokens to encode the chunk with, given the repeated offsets `r` before
+/// it, returning them along with the repeated offsets after them.
+pub fn parse(
+    input: &Input,
+    table: &MatchTable,
+    r: [u32; 3],
+    costs: &Costs,
+) -> (Vec<Token>, [u32; 3]) {
+    let size = input.history.len() - input.start;
+    let mut nodes = vec![
+        Node {
+            cost: u32::MAX,
+            token: Token::Literal(0),
+            len: 0,
+            r,
+        };
+        size + 1
+    ];
+    nodes[0].cost = 0;
+
+    let mut i = 0;
+    while i < size {
+        let node = nodes[i];
+        let pos = input.start + i;
+        let mut relax = |len: usize, cost: u32, token: Token, r: [u32; 3]| {
+            let next = &mut nodes[i + len];
+            let cost = node.cost + cost;
+            if cost < next.cost {
+                *next = Node {
+                    cost,
+                    token,
+                    len: len as u32,
+                    r,
+                };
+            }
+        };
+
+        let max_len = (size - i).min(input.max_match);
+        let max_offset = input.max_offset.min(pos);
+
+        // Matches at a repeated offset use its slot instead, like the encoder does, so the
+        // first slot with the offset is the only one worth trying.
+        let repeated: [usize; 3] = core::array::from_fn(|slot| {
+            let offset = node.r[slot];
+            if offset as usize <= max_offset && !node.r[..slot].contains(&offset) {
+                input.match_len(pos, offset as usize, max_len)
+            } else {
+                0
+            }
+        });
+        let matches = table.get(i);
+
+        // A match long enough is taken right away, skipping the positions it covers, which
+        // would be slow to go through and are unlikely to lead anywhere better.
+        let (best_slot, &best_repeated) = repeated
+            .iter()
+            .enumerate()
+            .max_by_key(|&(slot, &len)| (len, core::cmp::Reverse(slot)))
+            .unwrap();
+        let longest = matches.last().map_or(0, |m| m.length as usize);
+        if best_repeated.max(longest) >= input.nice_len {
+            let (len, token, r) = if best_repeated >= longest {
+                let mut r = node.r;
+                r.swap(0, best_slot);
+                (best_repeated, repeated_token(best_repeated, best_slot), r)
+            } else {
+                let m = matches.last().unwrap();
+                (
+                    longest,
+                    match_token(longest, m.offset),
+                    push(node.r, m.offset),
+                )
+            };
+            relax(len, costs.token(token, input.delta), token, r);
+            i += len;
+            continue;
+        }
+
+        let value = input.history[pos];
+        relax(1, costs.literal(value), Token::Literal(value), node.r);
+
+        for (slot, &match_len) in repeated.iter().enumerate() {
+            let mut r = node.r;
+            r.swap(0, slot);
+            for len in lengths(2, match_len) {
+                let token = repeated_token(len, slot);
+                relax(len, costs.token(token, input.delta), token, r);
+            }
+        }
+
+        let mut prev_len = 1;
+        for m in matches {
+            if !node.r.contains(&m.offset) {
+                let r = push(node.r, m.offset);
+                for len in lengths(prev_len + 1, m.length as usize) {
+                    let token = match_token(len, m.offset);
+                    relax(len, costs.token(token, input.delta), token, r);
+                }
+            }
+            prev_len = m.length as usize;
+        }
+
+        i += 1;
+    }
+
+    let mut tokens = Vec::new();
+    let mut i = size;
+    while i > 0 {
+        tokens.push(nodes[i].token);
+        i -= nodes[i].len as usize;
+    }
+    tokens.reverse();
+    (tokens, nodes[size].r)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(history: &[u8]) -> Input<'_> {
+        Input {
+            history,
+            start: 0,
+            max_match: MAX_MATCH,
+            max_offset: 1 << 16,
+            nice_len: MAX_MATCH,
+            delta: false,
+        }
+    }
+
+    fn empty_table(len: usize) -> MatchTable {
+        let mut table = MatchTable::new();
+        (0..len).for_each(|_| table.push(&[]));
+        table
+    }
+
+    #[test]
+    fn prefers_repeated_offsets() {
+        // Every match after the first one is cheaper with the same offset.
+        let history = b"abcXabcYabcZabc";
+        let mut table = MatchTable::new();
+        for pos in 0..history.len() {
+            if pos >= 4 && pos % 4 == 0 {
+                table.push(&[Match {
+                    length: 3,
+                    offset: 4,
+                }]);
+            } else {
+                table.push(&[]);
+            }
+        }
+
+        let costs = Costs::initial(256 + 8 * 32, 249);
+        let (tokens, r) = parse(&input(history), &table, [1, 1, 1], &costs);
+        assert_eq!(r, [4, 1, 1]);
+
+        let slots = tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Match { position_slot, .. } => Some(*position_slot),
+                Token::Literal(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(slots.first().map(|&slot| slot >= 3), Some(true));
+        assert!(slots[1..].iter().all(|&slot| slot == 0));
+    }
+
+    #[test]
+    fn literals_when_cheaper() {
+        let history = b"abcdefgh";
+        let costs = Costs::initial(256 + 8 * 32, 249);
+        let (tokens, r) = parse(
+            &input(history),
+            &empty_table(history.len()),
+            [1, 2, 3],
+            &costs,
+        );
+        assert_eq!(
+            tokens,
+            history
+                .iter()
+                .map(|&b| Token::Literal(b))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(r, [1, 2, 3]);
+    }
+
+    #[test]
+    fn costs_follow_frequencies() {
+        let mut tokens = vec![Token::Literal(b'a'); 100];
+        tokens.push(Token::Literal(b'b'));
+        tokens.push(Token::Literal(b'c'));
+        let costs = Costs::from_tokens(&tokens, 256 + 8 * 32, 249);
+        assert!(costs.literal(b'a') < costs.literal(b'b'));
+        assert_eq!(costs.literal(b'z'), UNUSED_COST);
+    }
+}
@@ -0,0 +1,197 @@
+use std::io::{self, Read};
+
+use crate::{Framing, Lzxd, MAX_CHUNK_SIZE};
+
+/// A [`Read`] adapter that decompresses a framed LZXD stream.
+///
+/// Chunks are read from the inner reader one at a time, delimited according to the given
+/// [`Framing`], and fed to the decoder until the expected amount of uncompressed data has
+/// been produced.
+///
+/// ```no_run
+/// # fn main() -> std::io::Result<()> {
+/// use std::fs::File;
+/// use ::lzxd::{Framing, Lzxd, LzxdReader, WindowSize};
+///
+/// let input = File::open("data.lzx")?;
+/// let mut reader = LzxdReader::new(input, Lzxd::new(WindowSize::KB64), Framing::Xnb, 1234);
+/// std::io::copy(&mut reader, &mut File::create("data.bin")?)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Read`]: https://doc.rust-lang.org/std/io/trait.Read.html
+/// [`Framing`]: enum.Framing.html
+pub struct LzxdReader<R> {
+    /// The reader with the compressed data stream.
+    inner: R,
+
+    /// The decoder used to decompress each chunk.
+    lzxd: Lzxd,
+
+    /// How the compressed chunks are delimited.
+    framing: Framing,
+
+    /// How many uncompressed bytes are left to be decompressed.
+    remaining: u64,
+
+    /// Buffer holding the current compressed chunk.
+    chunk: Vec<u8>,
+
+    /// Buffer holding the current decompressed chunk.
+    output: Box<[u8]>,
+
+    /// The range of `output` which has not been read yet.
+    output_pos: usize,
+    output_len: usize,
+}
+
+impl<R: Read> LzxdReader<R> {
+    /// Creates a new reader decompressing the framed data from `inner` with the given decoder.
+    ///
+    /// The size of the data once decompressed, `uncompressed_len`, must be known beforehand,
+    /// because it determines the size of the last chunk when the [`Framing`] doesn't say.
+    ///
+    /// [`Framing`]: enum.Framing.html
+    pub fn new(inner: R, lzxd: Lzxd, framing: Framing, uncompressed_len: u64) -> Self {
+        Self {
+            inner,
+            lzxd,
+            framing,
+            remaining: uncompressed_len,
+            chunk: Vec::new(),
+            output: vec![0; MAX_CHUNK_SIZE].into_boxed_slice(),
+            output_pos: 0,
+            output_len: 0,
+        }
+    }
+
+    /// Returns a reference to the underlying reader.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Unwraps this `LzxdReader`, returning the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads and decompresses the next chunk into the output buffer.
+    fn fill_output(&mut self) -> io::Result<()> {
+        let mut header = [0; Framing::MAX_HEADER_LEN];
+        self.inner.read_exact(&mut header[..1])?;
+        let header_len = self.framing.header_len(header[0]);
+        self.inner.read_exact(&mut header[1..header_len])?;
+        let header = self.framing.parse_header(&header[..header_len]);
+
+        // An empty chunk would look like the end of the stream, and no chunk decompresses to
+        // more than the output buffer holds.
+        if matches!(header.uncompressed_len, Some(len) if len == 0 || len > MAX_CHUNK_SIZE) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid uncompressed chunk size",
+            ));
+        }
+
+        let output_len = header
+            .uncompressed_len
+            .unwrap_or(MAX_CHUNK_SIZE)
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+
+        self.chunk.resize(header.compressed_len, 0);
+        self.inner.read_exact(&mut self.chunk)?;
+
+        self.lzxd
+            .decompress_next_into(&self.chunk, &mut self.output[..output_len])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        self.output_pos = 0;
+        self.output_len = output_len;
+        self.remaining -= output_len as u64;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for LzxdReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.output_pos == self.output_len {
+            if self.remaining == 0 || buf.is_empty() {
+                return Ok(0);
+            }
+            self.fill_output()?;
+        }
+
+        let available = &self.output[self.output_pos..self.output_len];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.output_pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WindowSize;
+
+    // An uncompressed block of 6 bytes split into two chunks of 3 bytes each.
+    const FIRST_CHUNK: [u8; 19] = [
+        0x00, 0x30, 0x60, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        0x00, b'a', b'b', b'c',
+    ];
+    const SECOND_CHUNK: [u8; 3] = [b'd', b'e', b'f'];
+
+    #[test]
+    fn read_ms_patch() {
+        let data = [
+            0x14, 0x00, 0x00, 0x30, 0x30, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x00, 0x00, 0x00, b'a', b'b', b'c', 0x00,
+        ];
+
+        let lzxd = Lzxd::new(WindowSize::KB32);
+        let mut reader = LzxdReader::new(&data[..], lzxd, Framing::MsPatch, 3);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        assert_eq!(output, b"abc");
+    }
+
+    #[test]
+    fn read_xnb_uncompressed_sizes() {
+        let mut data = vec![0xFF, 0x00, 0x03, 0x00, FIRST_CHUNK.len() as u8];
+        data.extend(FIRST_CHUNK);
+        data.extend([0xFF, 0x00, 0x03, 0x00, SECOND_CHUNK.len() as u8]);
+        data.extend(SECOND_CHUNK);
+
+        let lzxd = Lzxd::new(WindowSize::KB32);
+        let mut reader = LzxdReader::new(&data[..], lzxd, Framing::Xnb, 6);
+        let mut output = Vec::new();
+        io::copy(&mut reader, &mut output).unwrap();
+        assert_eq!(output, b"abcdef");
+    }
+
+    #[test]
+    fn read_truncated() {
+        let mut data = vec![0xFF, 0x00, 0x03, 0x00, FIRST_CHUNK.len() as u8];
+        data.extend(FIRST_CHUNK);
+
+        let lzxd = Lzxd::new(WindowSize::KB32);
+        let mut reader = LzxdReader::new(&data[..], lzxd, Framing::Xnb, 6);
+        let mut output = Vec::new();
+        let err = reader.read_to_end(&mut output).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(output, b"abc");
+    }
+
+    #[test]
+    fn read_invalid_uncompressed_sizes() {
+        for data in [
+            [0xFF, 0xFF, 0xFF, 0x00, 0x02, 0x00, 0x00],
+            [0xFF, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00],
+        ] {
+            let lzxd = Lzxd::new(WindowSize::KB32);
+            let mut reader = LzxdReader::new(&data[..], lzxd, Framing::Xnb, 0x1_0000);
+            let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,425 @@
+use alloc::{vec, vec::Vec};
+use core::fmt;
+
+use crate::{
+    Block, BlockKind, CanonicalTree, DecoderState, Lzxd, PostProcessState, Tree, Variant, Window,
+    WindowSize, MAX_CHUNK_SIZE,
+};
+
+/// Magic bytes at the start of every serialized snapshot.
+const MAGIC: &[u8; 4] = b"LZXS";
+
+/// Version of the serialization format, bumped every time it changes.
+const VERSION: u8 = 1;
+
+/// The full state of an [`Lzxd`] decoder between two chunks.
+///
+/// A snapshot can be restored into a fresh decoder with [`Lzxd::from_snapshot`] to resume
+/// decompression at the chunk following the one that was last decompressed, without having to
+/// decompress the data before it again. Snapshots can also be serialized with
+/// [`Self::to_bytes`] to persist them.
+///
+/// ```no_run
+/// # fn get_compressed_chunk() -> Option<(Vec<u8>, usize)> { unimplemented!() }
+/// use ::lzxd::{Lzxd, LzxdSnapshot, WindowSize};
+///
+/// let mut lzxd = Lzxd::new(WindowSize::KB64);
+/// let (chunk, output_size) = get_compressed_chunk().unwrap();
+/// lzxd.decompress_next(&chunk, output_size).unwrap();
+/// let saved = lzxd.snapshot().to_bytes();
+///
+/// // Later on…
+/// let snapshot = LzxdSnapshot::from_bytes(&saved).unwrap();
+/// let mut lzxd = Lzxd::from_snapshot(&snapshot);
+/// let (chunk, output_size) = get_compressed_chunk().unwrap();
+/// lzxd.decompress_next(&chunk, output_size).unwrap();
+/// ```
+///
+/// [`Lzxd`]: struct.Lzxd.html
+/// [`Lzxd::from_snapshot`]: struct.Lzxd.html#method.from_snapshot
+#[derive(Clone)]
+pub struct LzxdSnapshot {
+    window: Window,
+    state: DecoderState,
+}
+
+/// The error type used when a serialized snapshot cannot be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The data does not start with the magic bytes of a snapshot.
+    InvalidMagic,
+
+    /// The snapshot was serialized with an unsupported version of the format.
+    UnsupportedVersion(u8),
+
+    /// The data ended before the snapshot was fully read.
+    UnexpectedEof,
+
+    /// The data contained a value that cannot be part of a valid snapshot.
+    InvalidData,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use SnapshotError::*;
+
+        match self {
+            InvalidMagic => write!(f, "data is not a snapshot"),
+            UnsupportedVersion(version) => {
+                write!(f, "snapshot version {} is not supported", version)
+            }
+            UnexpectedEof => write!(f, "reached end of data without fully reading the snapshot"),
+            InvalidData => write!(f, "snapshot contains invalid data"),
+        }
+    }
+}
+
+impl core::error::Error for SnapshotError {}
+
+/// Little-endian reader over the serialized bytes.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn read(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < len {
+            return Err(SnapshotError::UnexpectedEof);
+        }
+        let (read, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(read)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::InvalidData),
+        }
+    }
+
+    fn read_u32(&mut self) -> Result<u32, SnapshotError> {
+        let bytes = self.read(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, SnapshotError> {
+        let lo = self.read_u32()? as u64;
+        let hi = self.read_u32()? as u64;
+        Ok(hi << 32 | lo)
+    }
+
+    fn read_usize(&mut self) -> Result<usize, SnapshotError> {
+        self.read_u64()?
+            .try_into()
+            .map_err(|_| SnapshotError::InvalidData)
+    }
+
+    fn read_path_lengths(&mut self, count: usize) -> Result<Vec<u8>, SnapshotError> {
+        let path_lengths = self.read(count)?;
+        if path_lengths.iter().any(|&len| len > 16) {
+            return Err(SnapshotError::InvalidData);
+        }
+        Ok(path_lengths.to_vec())
+    }
+
+    fn read_tree(&mut self, count: usize) -> Result<Tree, SnapshotError> {
+        Tree::from_path_lengths(self.read_path_lengths(count)?)
+            .map_err(|_| SnapshotError::InvalidData)
+    }
+
+    fn read_optional_tree(&mut self, count: usize) -> Result<Option<Tree>, SnapshotError> {
+        if self.read_bool()? {
+            self.read_tree(count).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+fn write_optional_tree(out: &mut Vec<u8>, tree: Option<&Tree>) {
+    match tree {
+        Some(tree) => {
+            out.push(1);
+            out.extend(tree.path_lengths());
+        }
+        None => out.push(0),
+    }
+}
+
+impl LzxdSnapshot {
+    /// The window size used by the decoder this snapshot was taken from.
+    pub fn window_size(&self) -> WindowSize {
+        self.state.window_size
+    }
+
+    /// The variant of the format used by the decoder this snapshot was taken from.
+    pub fn variant(&self) -> Variant {
+        self.state.variant
+    }
+
+    /// The amount of data that had been decompressed when this snapshot was taken.
+    pub fn decompressed_len(&self) -> usize {
+        self.state.chunk_offset
+    }
+
+    /// Serializes the snapshot into a versioned format that can be read back with
+    /// [`Self::from_bytes`], even by later versions of this crate.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let state = &self.state;
+        let mut out = Vec::with_capacity(self.window.buffer().len() + 4096);
+
+        out.extend(MAGIC);
+        out.push(VERSION);
+        out.extend((state.window_size as u32).to_le_bytes());
+        out.push(match state.variant {
+            Variant::Lzx => 0,
+            Variant::LzxDelta => 1,
+            Variant::Wim => 2,
+        });
+        state.r.iter().for_each(|r| out.extend(r.to_le_bytes()));
+        out.extend((state.chunk_offset as u64).to_le_bytes());
+        out.push(state.first_chunk_read as u8);
+        out.extend((state.chunk_count as u64).to_le_bytes());
+        out.extend((state.block_count as u64).to_le_bytes());
+        match &state.postprocess {
+            Some(postprocess) => {
+                out.push(1);
+                out.extend(postprocess.e8_translation_size.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+        out.extend(state.main_tree.path_lengths());
+        out.extend(state.length_tree.path_lengths());
+
+        let block = &state.current_block;
+        out.extend(block.remaining.to_le_bytes());
+        out.extend(block.size.to_le_bytes());
+        match &block.kind {
+            BlockKind::Verbatim {
+                main_tree,
+                length_tree,
+            } => {
+                out.push(0);
+                out.extend(main_tree.path_lengths());
+                write_optional_tree(&mut out, length_tree.as_ref());
+            }
+            BlockKind::AlignedOffset {
+                aligned_offset_tree,
+                main_tree,
+                length_tree,
+            } => {
+                out.push(1);
+                out.extend(aligned_offset_tree.path_lengths());
+                out.extend(main_tree.path_lengths());
+                write_optional_tree(&mut out, length_tree.as_ref());
+            }
+            BlockKind::Uncompressed { r } => {
+                out.push(2);
+                r.iter().for_each(|r| out.extend(r.to_le_bytes()));
+            }
+        }
+
+        out.extend((self.window.pos() as u32).to_le_bytes());
+        out.extend(self.window.buffer());
+        out
+    }
+
+    /// Deserializes a snapshot previously serialized with [`Self::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { bytes };
+
+        if reader.read(MAGIC.len())? != MAGIC {
+            return Err(SnapshotError::InvalidMagic);
+        }
+        match reader.read_u8()? {
+            VERSION => {}
+            version => return Err(SnapshotError::UnsupportedVersion(version)),
+        }
+
+        let window_size = WindowSize::from_value(reader.read_u32()? as usize)
+            .ok_or(SnapshotError::InvalidData)?;
+        let variant = match reader.read_u8()? {
+            0 => Variant::Lzx,
+            1 => Variant::LzxDelta,
+            2 => Variant::Wim,
+            _ => return Err(SnapshotError::InvalidData),
+        };
+
+        let mut state = DecoderState::new(window_size, variant);
+        let main_count = state.main_tree.path_lengths().len();
+        let length_count = state.length_tree.path_lengths().len();
+
+        state.r = [reader.read_u32()?, reader.read_u32()?, reader.read_u32()?];
+        state.chunk_offset = reader.read_usize()?;
+        state.first_chunk_read = reader.read_bool()?;
+        state.chunk_count = reader.read_usize()?;
+        state.block_count = reader.read_usize()?;
+        state.postprocess = if reader.read_bool()? {
+            Some(PostProcessState {
+                e8_translation_size: reader.read_u32()? as i32,
+                data_chunk: vec![0; MAX_CHUNK_SIZE].into_boxed_slice(),
+            })
+        } else {
+            None
+        };
+        state.main_tree = CanonicalTree::from_path_lengths(reader.read_path_lengths(main_count)?);
+        state.length_tree =
+            CanonicalTree::from_path_lengths(reader.read_path_lengths(length_count)?);
+
+        let remaining = reader.read_u32()?;
+        let size = reader.read_u32()?;
+        let kind = match reader.read_u8()? {
+            0 => BlockKind::Verbatim {
+                main_tree: reader.read_tree(main_count)?,
+                length_tree: reader.read_optional_tree(length_count)?,
+            },
+            1 => BlockKind::AlignedOffset {
+                aligned_offset_tree: reader.read_tree(8)?,
+                main_tree: reader.read_tree(main_count)?,
+                length_tree: reader.read_optional_tree(length_count)?,
+            },
+            2 => BlockKind::Uncompressed {
+                r: [reader.read_u32()?, reader.read_u32()?, reader.read_u32()?],
+            },
+            _ => return Err(SnapshotError::InvalidData),
+        };
+        if remaining > size || size >= 1 << 24 {
+            return Err(SnapshotError::InvalidData);
+        }
+        state.current_block = Block {
+            remaining,
+            size,
+            kind,
+        };
+
+        let mut window = window_size.create_buffer();
+        let pos = reader.read_u32()? as usize;
+        let buffer = reader.read(window.buffer().len())?;
+        if pos >= buffer.len() || !reader.bytes.is_empty() {
+            return Err(SnapshotError::InvalidData);
+        }
+        window.restore(pos, buffer);
+
+        Ok(Self { window, state })
+    }
+}
+
+impl Lzxd {
+    /// Captures the full state of the decoder, so that decompression can later resume from
+    /// this point with [`Self::from_snapshot`].
+    pub fn snapshot(&self) -> LzxdSnapshot {
+        LzxdSnapshot {
+            window: self.window.clone(),
+            state: self.state.clone(),
+        }
+    }
+
+    /// Creates a new instance of the decoder state from a [`LzxdSnapshot`], ready to
+    /// decompress the chunk following the last one decompressed before taking the snapshot.
+    ///
+    /// [`LzxdSnapshot`]: struct.LzxdSnapshot.html
+    pub fn from_snapshot(snapshot: &LzxdSnapshot) -> Self {
+        Self {
+            window: snapshot.window.clone(),
+            state: snapshot.state.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An uncompressed block of 6 bytes split into two chunks of 3 bytes each.
+    const FIRST_CHUNK: [u8; 19] = [
+        0x00, 0x30, 0x60, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+        0x00, b'a', b'b', b'c',
+    ];
+    const SECOND_CHUNK: [u8; 3] = [b'd', b'e', b'f'];
+
+    #[test]
+    fn restore_snapshot() {
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        assert_eq!(lzxd.decompress_next(&FIRST_CHUNK, 3).unwrap(), b"abc");
+
+        let snapshot = lzxd.snapshot();
+        assert_eq!(lzxd.decompress_next(&SECOND_CHUNK, 3).unwrap(), b"def");
+
+        let mut lzxd = Lzxd::from_snapshot(&snapshot);
+        assert_eq!(lzxd.decompress_next(&SECOND_CHUNK, 3).unwrap(), b"def");
+    }
+
+    #[test]
+    fn restore_serialized_snapshot() {
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        assert_eq!(lzxd.decompress_next(&FIRST_CHUNK, 3).unwrap(), b"abc");
+
+        let bytes = lzxd.snapshot().to_bytes();
+        let snapshot = LzxdSnapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(snapshot.window_size(), WindowSize::KB32);
+        assert_eq!(snapshot.decompressed_len(), 3);
+
+        let mut lzxd = Lzxd::from_snapshot(&snapshot);
+        assert_eq!(lzxd.decompress_next(&SECOND_CHUNK, 3).unwrap(), b"def");
+        assert_eq!(snapshot.to_bytes(), bytes);
+
+        // Chunks keep being counted from where the snapshot was taken.
+        let err = lzxd
+            .decompress_next(&[0x00, 0x00, 0x60, 0x00], 3)
+            .unwrap_err();
+        assert_eq!(err.chunk_index(), 2);
+        assert_eq!(err.block_index(), 1);
+    }
+
+    #[test]
+    fn serialize_verbatim_block() {
+        let mut lzxd = Lzxd::new(WindowSize::KB32);
+        lzxd.state.first_chunk_read = true;
+        lzxd.state.current_block = Block {
+            remaining: 1,
+            size: 2,
+            kind: BlockKind::Verbatim {
+                main_tree: Tree::from_path_lengths({
+                    let mut lengths = vec![0; 256 + 8 * 30];
+                    lengths[b'a' as usize] = 1;
+                    lengths[b'b' as usize] = 1;
+                    lengths
+                })
+                .unwrap(),
+                length_tree: None,
+            },
+        };
+
+        let snapshot = LzxdSnapshot::from_bytes(&lzxd.snapshot().to_bytes()).unwrap();
+        let mut lzxd = Lzxd::from_snapshot(&snapshot);
+        assert_eq!(lzxd.decompress_next(&[0x00, 0x80], 1).unwrap(), b"b");
+    }
+
+    #[test]
+    fn reject_invalid_snapshots() {
+        let bytes = Lzxd::new(WindowSize::KB32).snapshot().to_bytes();
+
+        assert_eq!(
+            LzxdSnapshot::from_bytes(b"nope").err(),
+            Some(SnapshotError::InvalidMagic)
+        );
+
+        let mut newer = bytes.clone();
+        newer[4] = VERSION + 1;
+        assert_eq!(
+            LzxdSnapshot::from_bytes(&newer).err(),
+            Some(SnapshotError::UnsupportedVersion(VERSION + 1))
+        );
+
+        assert_eq!(
+            LzxdSnapshot::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(SnapshotError::UnexpectedEof)
+        );
+    }
+}
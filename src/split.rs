@@ -0,0 +1,344 @@
+//! Splitting of the tokens produced by the encoder into blocks.
+//!
+//! Every block carries its own Huffman trees, so starting a new one pays off when the
+//! statistics of the data change enough that the bits saved by better fitting trees outweigh
+//! the bits needed to transmit them. Trees are delta-coded against the previous ones, so that
+//! cost depends on how much they change too.
+//!
+//! Tokens are first grouped into small runs of about the same size, which are then merged into
+//! the current block for as long as doing so is cheaper than starting a new one.
+use alloc::{vec, vec::Vec};
+use core::{mem, ops::Range};
+
+use crate::block::FOOTER_BITS;
+use crate::encoder::{write_tree, Token, MAX_MATCH, MAX_PATH_LENGTH};
+use crate::{huffman, BitWriter, BlockType};
+
+/// The largest size that fits in the 24 bits of the Block Size field.
+pub const MAX_BLOCK_SIZE: usize = (1 << 24) - 1;
+
+/// The size of the runs of tokens considered for a new block.
+const RUN_SIZE: usize = 4096;
+
+/// The aligned offset tree path lengths are output using 3 bits each.
+const MAX_ALIGNED_PATH_LENGTH: u8 = 7;
+
+/// The bits of the Block Type and Block Size fields.
+const HEADER_BITS: usize = 3 + 24;
+
+/// The path lengths of the trees of a compressed block.
+pub struct BlockTrees {
+    pub main: Vec<u8>,
+    pub length: Vec<u8>,
+    /// The aligned offset tree, if any match can use it.
+    pub aligned: Option<Vec<u8>>,
+}
+
+/// The cheapest way found to write a block.
+pub struct BlockCost {
+    pub block_type: BlockType,
+    pub bits: usize,
+    pub trees: BlockTrees,
+}
+
+/// How often every element appears in a block, along with the bits written verbatim.
+#[derive(Clone)]
+pub struct BlockStats {
+    main: Vec<u32>,
+    length: Vec<u32>,
+    aligned: [u32; 8],
+    /// The footer and Extra Length bits of the matches in a verbatim block.
+    verbatim_bits: usize,
+    /// The footer and Extra Length bits of the matches in an aligned offset block, without
+    /// those encoded with the aligned offset tree.
+    aligned_verbatim_bits: usize,
+    /// The size of the block once decompressed.
+    size: usize,
+}
+
+impl BlockStats {
+    pub fn new(main_elements: usize, length_elements: usize) -> Self {
+        Self {
+            main: vec![0; main_elements],
+            length: vec![0; length_elements],
+            aligned: [0; 8],
+            verbatim_bits: 0,
+            aligned_verbatim_bits: 0,
+            size: 0,
+        }
+    }
+
+    /// The statistics of the given tokens.
+    pub fn of(tokens: &[Token], main_elements: usize, length_elements: usize, delta: bool) -> Self {
+        let mut stats = Self::new(main_elements, length_elements);
+        tokens.iter().for_each(|&token| stats.add(token, delta));
+        stats
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Counts a token, which for matches of LZX DELTA may need an Extra Length field.
+    pub fn add(&mut self, token: Token, delta: bool) {
+        let (main_element, length_element) = token.elements();
+        self.main[main_element] += 1;
+        if let Some(length_element) = length_element {
+            self.length[length_element] += 1;
+        }
+        self.size += token.len();
+
+        if let Token::Match {
+            length,
+            position_slot,
+            ..
+        } = token
+        {
+            let mut extra_bits = 0;
+            if delta && length as usize >= MAX_MATCH {
+                extra_bits = match length as usize - MAX_MATCH {
+                    0..=255 => 1 + 8,
+                    256..=1279 => 2 + 10,
+                    1280..=5375 => 3 + 12,
+                    _ => 3 + 15,
+                };
+            }
+
+            let footer_bits = if position_slot >= 3 {
+                FOOTER_BITS[position_slot as usize] as usize
+            } else {
+                0
+            };
+            self.verbatim_bits += footer_bits + extra_bits;
+            match token.aligned_element() {
+                Some(aligned_element) => {
+                    self.aligned[aligned_element] += 1;
+                    self.aligned_verbatim_bits += footer_bits - 3 + extra_bits;
+                }
+                None => self.aligned_verbatim_bits += footer_bits + extra_bits,
+            }
+        }
+    }
+
+    /// Adds the statistics of the block that follows.
+    pub fn merge(&mut self, other: &Self) {
+        let add = |a: &mut [u32], b: &[u32]| a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+        add(&mut self.main, &other.main);
+        add(&mut self.length, &other.length);
+        add(&mut self.aligned, &other.aligned);
+        self.verbatim_bits += other.verbatim_bits;
+        self.aligned_verbatim_bits += other.aligned_verbatim_bits;
+        self.size += other.size;
+    }
+
+    /// Builds the trees of a compressed block with these statistics.
+    fn trees(&self) -> BlockTrees {
+        BlockTrees {
+            main: huffman::path_lengths(&self.main, MAX_PATH_LENGTH),
+            length: if self.length.iter().any(|&f| f != 0) {
+                huffman::path_lengths(&self.length, MAX_PATH_LENGTH)
+            } else {
+                vec![0; self.length.len()]
+            },
+            aligned: self
+                .aligned
+                .iter()
+                .any(|&f| f != 0)
+                .then(|| huffman::path_lengths(&self.aligned, MAX_ALIGNED_PATH_LENGTH)),
+        }
+    }
+
+    /// Finds the cheapest type of block to write with these statistics, when the previous
+    /// trees had the path lengths `prev_main` and `prev_length`.
+    pub fn cost(&self, prev_main: &[u8], prev_length: &[u8]) -> BlockCost {
+        let trees = self.trees();
+        let coded_bits = |freqs: &[u32], lengths: &[u8]| -> usize {
+            freqs
+                .iter()
+                .zip(lengths)
+                .map(|(&f, &len)| f as usize * len as usize)
+                .sum()
+        };
+
+        // The trees are written in full to measure them, since the pretree depends on them.
+        let mut scratch = BitWriter::new();
+        write_tree(
+            &mut scratch,
+            &mut prev_main[..256].to_vec(),
+            &trees.main[..256],
+        );
+        write_tree(
+            &mut scratch,
+            &mut prev_main[256..].to_vec(),
+            &trees.main[256..],
+        );
+        write_tree(&mut scratch, &mut prev_length.to_vec(), &trees.length);
+        let compressed_bits = HEADER_BITS
+            + scratch.bit_position()
+            + coded_bits(&self.main, &trees.main)
+            + coded_bits(&self.length, &trees.length);
+
+        let mut block_type = BlockType::Verbatim;
+        let mut bits = compressed_bits + self.verbatim_bits;
+        if let Some(aligned) = &trees.aligned {
+            let aligned_bits = compressed_bits
+                + 8 * 3
+                + coded_bits(&self.aligned, aligned)
+                + self.aligned_verbatim_bits;
+            if aligned_bits < bits {
+                block_type = BlockType::AlignedOffset;
+                bits = aligned_bits;
+            }
+        }
+
+        // The padding before the repeated offsets is assumed to be as long as it gets, and an
+        // odd size needs a byte of padding after the data.
+        let uncompressed_bits = HEADER_BITS + 16 + 3 * 32 + 8 * (self.size + self.size % 2);
+        if uncompressed_bits <= bits {
+            block_type = BlockType::Uncompressed;
+            bits = uncompressed_bits;
+        }
+
+        BlockCost {
+            block_type,
+            bits,
+            trees,
+        }
+    }
+}
+
+/// Splits the tokens into blocks of at most `max_block_size` bytes, returning the range of
+/// tokens of each one. The previous trees had the path lengths `prev_main` and `prev_length`.
+pub fn split(
+    tokens: &[Token],
+    prev_main: &[u8],
+    prev_length: &[u8],
+    delta: bool,
+    max_block_size: usize,
+) -> Vec<Range<usize>> {
+    let (main_elements, length_elements) = (prev_main.len(), prev_length.len());
+
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut stats = BlockStats::new(main_elements, length_elements);
+    for (i, &token) in tokens.iter().enumerate() {
+        stats.add(token, delta);
+        if stats.size >= RUN_SIZE || i + 1 == tokens.len() {
+            let next = BlockStats::new(main_elements, length_elements);
+            runs.push((start..i + 1, mem::replace(&mut stats, next)));
+            start = i + 1;
+        }
+    }
+
+    let mut runs = runs.into_iter();
+    let Some((mut range, mut stats)) = runs.next() else {
+        return Vec::new();
+    };
+
+    let mut blocks = Vec::new();
+    let mut prev_main = prev_main.to_vec();
+    let mut prev_length = prev_length.to_vec();
+    let mut cost = stats.cost(&prev_main, &prev_length);
+    for (run_range, run_stats) in runs {
+        // The trees the run would be delta-coded against if it started a new block.
+        let (next_main, next_length) = match cost.block_type {
+            BlockType::Uncompressed => (&prev_main, &prev_length),
+            _ => (&cost.trees.main, &cost.trees.length),
+        };
+        let run_cost = run_stats.cost(next_main, next_length);
+
+        if stats.size + run_stats.size <= max_block_size {
+            let mut merged = stats.clone();
+            merged.merge(&run_stats);
+            let merged_cost = merged.cost(&prev_main, &prev_length);
+            if merged_cost.bits <= cost.bits + run_cost.bits {
+                range.end = run_range.end;
+                stats = merged;
+                cost = merged_cost;
+                continue;
+            }
+        }
+
+        if cost.block_type != BlockType::Uncompressed {
+            prev_main = cost.trees.main;
+            prev_length = cost.trees.length;
+        }
+        blocks.push(range);
+        range = run_range;
+        stats = run_stats;
+        cost = run_cost;
+    }
+    blocks.push(range);
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAIN_ELEMENTS: usize = 256 + 8 * 32;
+    const LENGTH_ELEMENTS: usize = 249;
+
+    fn literals(data: &[u8]) -> Vec<Token> {
+        data.iter().map(|&value| Token::Literal(value)).collect()
+    }
+
+    fn split_literals(data: &[u8]) -> Vec<Range<usize>> {
+        split(
+            &literals(data),
+            &[0; MAIN_ELEMENTS],
+            &[0; LENGTH_ELEMENTS],
+            false,
+            MAX_BLOCK_SIZE,
+        )
+    }
+
+    #[test]
+    fn uniform_data_is_one_block() {
+        let data = b"abcd".repeat(4 * RUN_SIZE);
+        let blocks = split_literals(&data);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0], 0..data.len());
+    }
+
+    #[test]
+    fn split_where_statistics_change() {
+        let mut data = b"ab".repeat(4 * RUN_SIZE);
+        data.extend(b"xyz".repeat(3 * RUN_SIZE));
+        assert_eq!(
+            split_literals(&data),
+            [0..8 * RUN_SIZE, 8 * RUN_SIZE..data.len()]
+        );
+    }
+
+    #[test]
+    fn block_size_is_limited() {
+        let tokens = vec![
+            Token::Match {
+                length: MAX_MATCH as u32,
+                position_slot: 0,
+                footer: 0,
+            };
+            MAX_BLOCK_SIZE / MAX_MATCH + 100
+        ];
+        let blocks = split(
+            &tokens,
+            &[0; MAIN_ELEMENTS],
+            &[0; LENGTH_ELEMENTS],
+            false,
+            MAX_BLOCK_SIZE,
+        );
+        assert_eq!(blocks.len(), 2);
+        assert!(blocks
+            .iter()
+            .all(|block| block.len() * MAX_MATCH <= MAX_BLOCK_SIZE));
+    }
+
+    #[test]
+    fn cost_prefers_uncompressed_for_noise() {
+        let data = (0..=255).cycle().take(RUN_SIZE).collect::<Vec<u8>>();
+        let stats = BlockStats::of(&literals(&data), MAIN_ELEMENTS, LENGTH_ELEMENTS, false);
+        let cost = stats.cost(&[0; MAIN_ELEMENTS], &[0; LENGTH_ELEMENTS]);
+        assert_eq!(cost.block_type, BlockType::Uncompressed);
+    }
+}
@@ -1,12 +1,13 @@
-use std::fmt;
-use std::num::NonZeroU8;
-use std::ops::Range;
+use alloc::{vec, vec::Vec};
+use core::fmt;
+use core::num::NonZeroU8;
+use core::ops::Range;
 
 use crate::{Bitstream, DecodeFailed};
 
 /// The canonical tree cannot be used to decode elements. Instead, it behaves like a builder for
 /// instances of the actual tree that can decode elements efficiently.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CanonicalTree {
     // > Each tree element can have a path length of [0, 16], where a zero path length indicates
     // > that the element has a zero frequency and is not present in the tree.
@@ -15,6 +16,7 @@ pub struct CanonicalTree {
     path_lengths: Vec<u8>,
 }
 
+#[derive(Clone)]
 pub struct Tree {
     path_lengths: Vec<u8>,
     largest_length: NonZeroU8,
@@ -30,6 +32,15 @@ impl CanonicalTree {
         }
     }
 
+    /// Create a new canonical tree directly from known path lengths.
+    pub fn from_path_lengths(path_lengths: Vec<u8>) -> Self {
+        Self { path_lengths }
+    }
+
+    pub fn path_lengths(&self) -> &[u8] {
+        &self.path_lengths
+    }
+
     /// Create a new `Tree` instance from this cast that can be used to decode elements. If the
     /// resulting tree is empty (all path lengths are 0), then `Ok(None)` is returned.
     ///
@@ -188,6 +199,10 @@ impl Tree {
         CanonicalTree { path_lengths }.create_instance()
     }
 
+    pub fn path_lengths(&self) -> &[u8] {
+        &self.path_lengths
+    }
+
     pub fn decode_element(&self, bitstream: &mut Bitstream) -> Result<u16, DecodeFailed> {
         // Perform the inverse translation, peeking as many bits as our tree is…
         let code = self.huffman_tree[bitstream.peek_bits(self.largest_length.get()) as usize];
@@ -0,0 +1,56 @@
+use crate::BlockInfo;
+
+/// An observer of the tokens found while decoding a data stream, useful to inspect what a
+/// compressor produced.
+///
+/// Every method does nothing by default, so only the relevant ones need to be implemented.
+/// The visitor is only called by [`Lzxd::decompress_next_with_visitor`], so there is no cost
+/// when it's not used.
+///
+/// ```no_run
+/// # fn get_compressed_chunk() -> Option<(Vec<u8>, usize)> { unimplemented!() }
+/// use ::lzxd::{Lzxd, Visitor, WindowSize};
+///
+/// #[derive(Default)]
+/// struct MatchCounter(usize);
+///
+/// impl Visitor for MatchCounter {
+///     fn on_match(&mut self, _offset: usize, _length: usize, _repeated_slot: Option<u8>) {
+///         self.0 += 1;
+///     }
+/// }
+///
+/// let mut lzxd = Lzxd::new(WindowSize::KB64);
+/// let mut counter = MatchCounter::default();
+///
+/// while let Some((chunk, output_size)) = get_compressed_chunk() {
+///     lzxd.decompress_next_with_visitor(&chunk, output_size, &mut counter)
+///         .unwrap();
+/// }
+/// println!("found {} matches", counter.0);
+/// ```
+///
+/// [`Lzxd::decompress_next_with_visitor`]: struct.Lzxd.html#method.decompress_next_with_visitor
+#[allow(unused_variables)]
+pub trait Visitor {
+    /// Called when a new block starts, after its header (and trees, if any) have been read.
+    fn on_block_start(&mut self, block: &BlockInfo<'_>) {}
+
+    /// Called when a literal byte is decoded.
+    fn on_literal(&mut self, value: u8) {}
+
+    /// Called when a match is decoded. If the match used one of the repeated offsets, its
+    /// slot (0, 1 or 2) is given as well.
+    fn on_match(&mut self, offset: usize, length: usize, repeated_slot: Option<u8>) {}
+
+    /// Called when a run of data is copied from an uncompressed block. A single uncompressed
+    /// block may be split into several runs if it spans multiple chunks.
+    fn on_uncompressed(&mut self, len: usize) {}
+
+    /// Called when E8 translation modifies the call instruction at position `pos` of the
+    /// decompressed data.
+    fn on_e8_fixup(&mut self, pos: usize) {}
+}
+
+/// The unit visitor ignores all tokens.
+impl Visitor for () {}
@@ -0,0 +1,642 @@
+//! Reading of the resources of Windows Imaging (WIM) files.
+//!
+//! A WIM file holds one or more images of a file system, whose data is stored in resources
+//! identified by the SHA-1 hash of their contents, as listed by the lookup table. Every image
+//! also has a metadata resource describing its directory tree and which resource holds the
+//! contents of each file, which this module leaves to the caller to parse.
+//!
+//! Compressed resources are split into chunks of 32 KB once decompressed, each compressed on
+//! its own with [`Variant::Wim`] and preceded by a table with the offset of every chunk. Chunks
+//! that would not get any smaller are stored as-is instead.
+//!
+//! Only LZX compressed WIM files with the usual chunk size can be read, as produced by
+//! ImageX and DISM with maximum compression, and not those using XPRESS or LZMS. Only the
+//! resources stored in this part of a split WIM can be read, and their hashes are not
+//! verified.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use ::lzxd::wim::Wim;
+//!
+//! let data = std::fs::read("install.wim")?;
+//! let wim = Wim::new(&data)?;
+//! println!("{} images", wim.image_count());
+//! for entry in wim.metadata() {
+//!     let metadata = wim.read(entry.resource())?;
+//!     println!("image metadata of {} bytes", metadata.len());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Variant::Wim`]: ../enum.Variant.html#variant.Wim
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::bytes::ByteReader;
+use crate::{DecompressError, Lzxd, Variant, WindowSize, MAX_CHUNK_SIZE};
+
+/// Magic bytes at the start of every WIM file.
+const MAGIC: &[u8; 8] = b"MSWIM\0\0\0";
+
+/// The resources of the WIM file may be compressed.
+const FLAG_COMPRESSION: u32 = 0x0000_0002;
+
+/// Compressed resources use XPRESS.
+const FLAG_COMPRESS_XPRESS: u32 = 0x0002_0000;
+
+/// Compressed resources use LZX.
+const FLAG_COMPRESS_LZX: u32 = 0x0004_0000;
+
+/// Compressed resources use LZMS.
+const FLAG_COMPRESS_LZMS: u32 = 0x0008_0000;
+
+/// The resource holds the metadata of an image.
+const RESOURCE_FLAG_METADATA: u8 = 0x02;
+
+/// The resource is compressed.
+const RESOURCE_FLAG_COMPRESSED: u8 = 0x04;
+
+/// The resource is split across several parts of a split WIM.
+const RESOURCE_FLAG_SPANNED: u8 = 0x08;
+
+/// The resource holds several others compressed together.
+const RESOURCE_FLAG_SOLID: u8 = 0x10;
+
+/// The length of an entry of the lookup table.
+const LOOKUP_ENTRY_LEN: usize = 50;
+
+/// The window size used to compress the chunks of resources.
+const WINDOW_SIZE: WindowSize = WindowSize::KB32;
+
+/// The error type used when a WIM file cannot be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WimError {
+    /// The data does not start with the magic bytes of a WIM file.
+    InvalidMagic,
+
+    /// The data ended before the file was fully read.
+    UnexpectedEof,
+
+    /// The file contains a value that doesn't make sense, such as a chunk outside of its
+    /// resource.
+    InvalidData,
+
+    /// The lookup table has no resource with the requested hash.
+    ResourceNotFound,
+
+    /// The resource is solid or spans several parts, which is not supported.
+    UnsupportedResource,
+
+    /// The resources are compressed with a method or chunk size that is not supported.
+    UnsupportedCompression(Compression),
+
+    /// The LZX compressed data of a resource could not be decompressed.
+    Decompress(DecompressError),
+}
+
+impl fmt::Display for WimError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use WimError::*;
+
+        match self {
+            InvalidMagic => write!(f, "data is not a WIM file"),
+            UnexpectedEof => write!(f, "reached end of data without fully reading the WIM file"),
+            InvalidData => write!(f, "WIM file contains invalid data"),
+            ResourceNotFound => write!(f, "resource not found in the WIM file"),
+            UnsupportedResource => write!(f, "solid and spanned resources are not supported"),
+            UnsupportedCompression(compression) => {
+                write!(f, "{:?} compression is not supported", compression)
+            }
+            Decompress(e) => write!(f, "failed to decompress resource: {}", e),
+        }
+    }
+}
+
+impl core::error::Error for WimError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Decompress(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecompressError> for WimError {
+    fn from(e: DecompressError) -> Self {
+        Self::Decompress(e)
+    }
+}
+
+/// The compression method of the resources, from the flags of the header.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Compression {
+    /// The resources are stored as-is.
+    None,
+
+    /// The resources are compressed with XPRESS, which is not supported.
+    Xpress,
+
+    /// The resources are compressed with LZX in chunks of the given size.
+    Lzx(u32),
+
+    /// The resources are compressed with LZMS, which is not supported.
+    Lzms,
+
+    /// The flags say the resources are compressed, but not with a known method.
+    Unknown(u32),
+}
+
+impl Compression {
+    fn from_flags(flags: u32, chunk_size: u32) -> Self {
+        if flags & FLAG_COMPRESSION == 0 {
+            Self::None
+        } else if flags & FLAG_COMPRESS_LZX != 0 {
+            // Older files leave the chunk size out, as it could only be 32 KB.
+            Self::Lzx(if chunk_size == 0 {
+                MAX_CHUNK_SIZE as u32
+            } else {
+                chunk_size
+            })
+        } else if flags & FLAG_COMPRESS_XPRESS != 0 {
+            Self::Xpress
+        } else if flags & FLAG_COMPRESS_LZMS != 0 {
+            Self::Lzms
+        } else {
+            Self::Unknown(flags)
+        }
+    }
+}
+
+/// The location of a resource in the WIM file, from a resource header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resource {
+    size: u64,
+    flags: u8,
+    offset: u64,
+    original_size: u64,
+}
+
+impl Resource {
+    fn read(reader: &mut ByteReader<WimError>) -> Result<Self, WimError> {
+        // The size only takes 7 bytes, with the flags in the last one.
+        let size_and_flags = reader.read_u64()?;
+        Ok(Self {
+            size: size_and_flags & 0x00FF_FFFF_FFFF_FFFF,
+            flags: (size_and_flags >> 56) as u8,
+            offset: reader.read_u64()?,
+            original_size: reader.read_u64()?,
+        })
+    }
+
+    /// The size of the resource as stored in the file.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The offset of the resource from the start of the file.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The size of the resource once decompressed.
+    pub fn original_size(&self) -> u64 {
+        self.original_size
+    }
+
+    /// Whether the resource is compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.flags & RESOURCE_FLAG_COMPRESSED != 0
+    }
+
+    /// Whether the resource holds the metadata of an image.
+    pub fn is_metadata(&self) -> bool {
+        self.flags & RESOURCE_FLAG_METADATA != 0
+    }
+}
+
+/// An entry of the lookup table of a WIM file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceEntry {
+    resource: Resource,
+    part_number: u16,
+    ref_count: u32,
+    hash: [u8; 20],
+}
+
+impl ResourceEntry {
+    /// The location of the resource.
+    pub fn resource(&self) -> &Resource {
+        &self.resource
+    }
+
+    /// The part of a split WIM the resource is stored in, starting at 1.
+    pub fn part_number(&self) -> u16 {
+        self.part_number
+    }
+
+    /// How many files of the images have the contents of the resource.
+    pub fn ref_count(&self) -> u32 {
+        self.ref_count
+    }
+
+    /// The SHA-1 hash of the contents of the resource.
+    pub fn hash(&self) -> &[u8; 20] {
+        &self.hash
+    }
+}
+
+/// A WIM file, read from a buffer holding all of its data.
+#[derive(Debug, Clone)]
+pub struct Wim<'a> {
+    data: &'a [u8],
+    compression: Compression,
+    part_number: u16,
+    total_parts: u16,
+    image_count: u32,
+    boot_index: u32,
+    xml_data: Resource,
+    entries: Vec<ResourceEntry>,
+}
+
+impl<'a> Wim<'a> {
+    /// Reads the header and lookup table of the WIM file.
+    pub fn new(data: &'a [u8]) -> Result<Self, WimError> {
+        let mut reader = ByteReader::new(data, WimError::UnexpectedEof);
+        if reader.read(8)? != MAGIC {
+            return Err(WimError::InvalidMagic);
+        }
+        reader.read_u32()?; // header size
+        reader.read_u32()?; // version
+        let flags = reader.read_u32()?;
+        let chunk_size = reader.read_u32()?;
+        reader.read(16)?; // GUID
+        let part_number = reader.read_u16()?;
+        let total_parts = reader.read_u16()?;
+        let image_count = reader.read_u32()?;
+        let lookup_table = Resource::read(&mut reader)?;
+        let xml_data = Resource::read(&mut reader)?;
+        Resource::read(&mut reader)?; // boot metadata
+        let boot_index = reader.read_u32()?;
+
+        let mut this = Self {
+            data,
+            compression: Compression::from_flags(flags, chunk_size),
+            part_number,
+            total_parts,
+            image_count,
+            boot_index,
+            xml_data,
+            entries: Vec::new(),
+        };
+
+        let lookup_table = this.read(&lookup_table)?;
+        if lookup_table.len() % LOOKUP_ENTRY_LEN != 0 {
+            return Err(WimError::InvalidData);
+        }
+        let mut reader = ByteReader::new(&lookup_table[..], WimError::UnexpectedEof);
+        this.entries = (0..lookup_table.len() / LOOKUP_ENTRY_LEN)
+            .map(|_| {
+                Ok(ResourceEntry {
+                    resource: Resource::read(&mut reader)?,
+                    part_number: reader.read_u16()?,
+                    ref_count: reader.read_u32()?,
+                    hash: reader.read_array()?,
+                })
+            })
+            .collect::<Result<_, WimError>>()?;
+        Ok(this)
+    }
+
+    /// The compression method of the resources.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// The number of this part of a split WIM, starting at 1.
+    pub fn part_number(&self) -> u16 {
+        self.part_number
+    }
+
+    /// How many parts the WIM is split into, which is 1 if it's not split.
+    pub fn total_parts(&self) -> u16 {
+        self.total_parts
+    }
+
+    /// How many images the WIM holds.
+    pub fn image_count(&self) -> u32 {
+        self.image_count
+    }
+
+    /// The index of the bootable image, starting at 1, or 0 if there's none.
+    pub fn boot_index(&self) -> u32 {
+        self.boot_index
+    }
+
+    /// The entries of the lookup table, in the order they're listed.
+    pub fn entries(&self) -> &[ResourceEntry] {
+        &self.entries
+    }
+
+    /// The entries of the metadata resources of the images, in order.
+    pub fn metadata(&self) -> impl Iterator<Item = &ResourceEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.resource.is_metadata())
+    }
+
+    /// Finds the entry of the resource with the given SHA-1 hash.
+    pub fn find(&self, hash: &[u8; 20]) -> Option<&ResourceEntry> {
+        self.entries.iter().find(|entry| &entry.hash == hash)
+    }
+
+    /// Reads the XML data describing the images, which is encoded as UTF-16.
+    pub fn xml_data(&self) -> Result<Vec<u8>, WimError> {
+        self.read(&self.xml_data)
+    }
+
+    /// Reads the contents of the resource, decompressing it if needed.
+    pub fn read(&self, resource: &Resource) -> Result<Vec<u8>, WimError> {
+        if resource.flags & (RESOURCE_FLAG_SPANNED | RESOURCE_FLAG_SOLID) != 0 {
+            return Err(WimError::UnsupportedResource);
+        }
+
+        let data = resource
+            .offset
+            .checked_add(resource.size)
+            .and_then(|end| {
+                let start = usize::try_from(resource.offset).ok()?;
+                self.data.get(start..usize::try_from(end).ok()?)
+            })
+            .ok_or(WimError::UnexpectedEof)?;
+        let original_size =
+            usize::try_from(resource.original_size).map_err(|_| WimError::InvalidData)?;
+
+        if !resource.is_compressed() {
+            return if data.len() == original_size {
+                Ok(data.to_vec())
+            } else {
+                Err(WimError::InvalidData)
+            };
+        }
+
+        match self.compression {
+            Compression::Lzx(chunk_size) if chunk_size as usize == MAX_CHUNK_SIZE => {}
+            Compression::None => return Err(WimError::InvalidData),
+            compression => return Err(WimError::UnsupportedCompression(compression)),
+        }
+
+        // The table has the offset of every chunk but the first, from the end of the table.
+        // Resources of 4 GB or more need offsets of 8 bytes.
+        let chunk_count = original_size.div_ceil(MAX_CHUNK_SIZE);
+        let entry_len = if resource.original_size > u32::MAX as u64 {
+            8
+        } else {
+            4
+        };
+        // Checking that the table fits also bounds the sizes from the header that are used
+        // to allocate the offsets and output.
+        let table_len = chunk_count
+            .saturating_sub(1)
+            .checked_mul(entry_len)
+            .filter(|&len| len <= data.len())
+            .ok_or(WimError::InvalidData)?;
+        let mut reader = ByteReader::new(data, WimError::UnexpectedEof);
+        let mut offsets = Vec::with_capacity(chunk_count);
+        offsets.push(0);
+        for _ in 1..chunk_count {
+            let offset = match entry_len {
+                8 => reader.read_u64()?,
+                _ => reader.read_u32()? as u64,
+            };
+            offsets.push(usize::try_from(offset).map_err(|_| WimError::InvalidData)?);
+        }
+        let chunks = &data[table_len..];
+
+        let mut output = Vec::with_capacity(original_size);
+        let mut lzxd = Lzxd::with_variant(WINDOW_SIZE, Variant::Wim);
+        for (i, &start) in offsets.iter().enumerate() {
+            let end = offsets.get(i + 1).copied().unwrap_or(chunks.len());
+            let chunk = chunks.get(start..end).ok_or(WimError::InvalidData)?;
+            let chunk_len = (original_size - i * MAX_CHUNK_SIZE).min(MAX_CHUNK_SIZE);
+
+            // Every chunk is compressed on its own, unless it's stored as-is.
+            if chunk.len() == chunk_len {
+                output.extend_from_slice(chunk);
+            } else {
+                lzxd.reset();
+                output.extend_from_slice(lzxd.decompress_next(chunk, chunk_len)?);
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Reads the contents of the resource with the given SHA-1 hash.
+    pub fn extract(&self, hash: &[u8; 20]) -> Result<Vec<u8>, WimError> {
+        let entry = self.find(hash).ok_or(WimError::ResourceNotFound)?;
+        self.read(&entry.resource)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LzxdEncoder;
+
+    /// Compresses the resource in chunks, storing those that don't get smaller as-is.
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut encoder = LzxdEncoder::with_variant(WINDOW_SIZE, Variant::Wim);
+        let mut table = Vec::new();
+        let mut chunks: Vec<u8> = Vec::new();
+        for (i, chunk) in data.chunks(MAX_CHUNK_SIZE).enumerate() {
+            if i != 0 {
+                table.extend((chunks.len() as u32).to_le_bytes());
+            }
+            encoder.reset();
+            let compressed = encoder.compress_next(chunk).unwrap();
+            if compressed.len() < chunk.len() {
+                chunks.extend(compressed);
+            } else {
+                chunks.extend(chunk);
+            }
+        }
+        table.extend(chunks);
+        table
+    }
+
+    /// The header of a resource of `size` bytes at the given offset.
+    fn resource_header(offset: usize, size: usize, flags: u8, original_size: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend((size as u64 | (flags as u64) << 56).to_le_bytes());
+        out.extend((offset as u64).to_le_bytes());
+        out.extend((original_size as u64).to_le_bytes());
+        out
+    }
+
+    /// Builds a WIM file with the given `flags` and resources, along with the resource flags
+    /// they're stored with. The hash of every resource is its index repeated.
+    fn build(flags: u32, resources: &[(&[u8], u8)]) -> Vec<u8> {
+        let image_count = resources
+            .iter()
+            .filter(|(_, flags)| flags & RESOURCE_FLAG_METADATA != 0)
+            .count();
+        let mut out = MAGIC.to_vec();
+        out.extend(208u32.to_le_bytes());
+        out.extend(0x10D00u32.to_le_bytes());
+        out.extend(flags.to_le_bytes());
+        out.extend((MAX_CHUNK_SIZE as u32).to_le_bytes());
+        out.extend([0; 16]);
+        out.extend(1u16.to_le_bytes());
+        out.extend(1u16.to_le_bytes());
+        out.extend((image_count as u32).to_le_bytes());
+        out.resize(0x78, 0);
+        out.extend(1u32.to_le_bytes());
+        out.resize(208, 0);
+
+        let mut lookup_table = Vec::new();
+        for (i, &(data, flags)) in resources.iter().enumerate() {
+            let stored = if flags & RESOURCE_FLAG_COMPRESSED != 0 {
+                compress(data)
+            } else {
+                data.to_vec()
+            };
+            lookup_table.extend(resource_header(out.len(), stored.len(), flags, data.len()));
+            lookup_table.extend(1u16.to_le_bytes());
+            lookup_table.extend(1u32.to_le_bytes());
+            lookup_table.extend([i as u8; 20]);
+            out.extend(stored);
+        }
+
+        let xml = "<WIM></WIM>"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        for (header, data) in [(0x30, lookup_table), (0x48, xml)] {
+            let resource_header = resource_header(out.len(), data.len(), 0, data.len());
+            out[header..header + 24].copy_from_slice(&resource_header);
+            out.extend(data);
+        }
+        out
+    }
+
+    fn content(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i % 251) as u8 ^ (i / 700) as u8)
+            .collect()
+    }
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut seed = 7u32;
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn read_resources() {
+        let content = content(3 * MAX_CHUNK_SIZE + 100);
+        let noise = noise(MAX_CHUNK_SIZE + 5000);
+        let data = build(
+            FLAG_COMPRESSION | FLAG_COMPRESS_LZX,
+            &[
+                (
+                    b"metadata",
+                    RESOURCE_FLAG_METADATA | RESOURCE_FLAG_COMPRESSED,
+                ),
+                (&content, RESOURCE_FLAG_COMPRESSED),
+                (&noise, RESOURCE_FLAG_COMPRESSED),
+                (b"stored as-is", 0),
+            ],
+        );
+
+        let wim = Wim::new(&data).unwrap();
+        assert_eq!(wim.compression(), Compression::Lzx(MAX_CHUNK_SIZE as u32));
+        assert_eq!(wim.part_number(), 1);
+        assert_eq!(wim.total_parts(), 1);
+        assert_eq!(wim.image_count(), 1);
+        assert_eq!(wim.boot_index(), 1);
+        assert_eq!(wim.entries().len(), 4);
+
+        let metadata = wim.metadata().collect::<Vec<_>>();
+        assert_eq!(metadata.len(), 1);
+        assert_eq!(wim.read(metadata[0].resource()).unwrap(), b"metadata");
+
+        let entry = wim.find(&[1; 20]).unwrap();
+        assert!(entry.resource().is_compressed());
+        assert!((entry.resource().size() as usize) < content.len());
+        assert_eq!(entry.resource().original_size() as usize, content.len());
+        assert_eq!(wim.extract(&[1; 20]).unwrap(), content);
+        assert_eq!(wim.extract(&[2; 20]).unwrap(), noise);
+        assert_eq!(wim.extract(&[3; 20]).unwrap(), b"stored as-is");
+
+        let xml = wim.xml_data().unwrap();
+        assert_eq!(xml[..6], [b'<', 0, b'W', 0, b'I', 0]);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            Wim::new(b"MSWIM\0\0\x01").err(),
+            Some(WimError::InvalidMagic)
+        );
+        assert_eq!(
+            Wim::new(b"MSWIM\0\0\0").err(),
+            Some(WimError::UnexpectedEof)
+        );
+
+        let content = content(2 * MAX_CHUNK_SIZE);
+        let data = build(
+            FLAG_COMPRESSION | FLAG_COMPRESS_LZX,
+            &[(&content, RESOURCE_FLAG_COMPRESSED)],
+        );
+        let wim = Wim::new(&data).unwrap();
+        assert_eq!(wim.extract(&[1; 20]), Err(WimError::ResourceNotFound));
+
+        // The second chunk starting past the end of the resource.
+        let mut broken = data.clone();
+        broken[208..212].copy_from_slice(&0xFFFFu32.to_le_bytes());
+        let wim = Wim::new(&broken).unwrap();
+        assert_eq!(wim.extract(&[0; 20]), Err(WimError::InvalidData));
+
+        let mut resource = *wim.entries()[0].resource();
+        resource.flags |= RESOURCE_FLAG_SOLID;
+        assert_eq!(wim.read(&resource), Err(WimError::UnsupportedResource));
+
+        // A compressed lookup table far too large for its chunk table to fit in the file.
+        let mut broken = data.clone();
+        broken[0x37] |= RESOURCE_FLAG_COMPRESSED;
+        broken[0x40..0x48].copy_from_slice(&(1u64 << 62).to_le_bytes());
+        assert_eq!(Wim::new(&broken).err(), Some(WimError::InvalidData));
+
+        let data = build(
+            FLAG_COMPRESSION | FLAG_COMPRESS_XPRESS,
+            &[(&content, RESOURCE_FLAG_COMPRESSED)],
+        );
+        let wim = Wim::new(&data).unwrap();
+        assert_eq!(
+            wim.extract(&[0; 20]),
+            Err(WimError::UnsupportedCompression(Compression::Xpress))
+        );
+    }
+
+    #[test]
+    fn compression_from_flags() {
+        assert_eq!(Compression::from_flags(0, 0), Compression::None);
+        assert_eq!(
+            Compression::from_flags(FLAG_COMPRESSION | FLAG_COMPRESS_LZX, 0),
+            Compression::Lzx(MAX_CHUNK_SIZE as u32)
+        );
+        assert_eq!(
+            Compression::from_flags(FLAG_COMPRESSION | FLAG_COMPRESS_LZMS, 1 << 26),
+            Compression::Lzms
+        );
+        assert_eq!(
+            Compression::from_flags(FLAG_COMPRESSION, 0),
+            Compression::Unknown(FLAG_COMPRESSION)
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use alloc::{boxed::Box, vec, vec::Vec};
+
 use crate::{Bitstream, DecodeFailed, MAX_CHUNK_SIZE};
 
 /// The window size is not stored in the compressed data stream and must be known before
@@ -38,11 +40,35 @@ pub enum WindowSize {
 ///
 /// A `std::collections::VecDeque` is not used because the `deque_make_contiguous` feature
 /// is [nightly-only experimental](https://github.com/rust-lang/rust/issues/70929).
+#[derive(Clone)]
 pub struct Window {
     pos: usize,
     buffer: Box<[u8]>,
 }
 
+/// A linear buffer holding the entire decompressed data, used when its size is known up front.
+///
+/// Because nothing is ever overwritten, there is no need to rotate the buffer in order to
+/// provide a contiguous view of each chunk, and matches are copied without wrapping around.
+pub struct FlatWindow {
+    pos: usize,
+    window_size: usize,
+    buffer: Vec<u8>,
+}
+
+/// The destination of decoded data, which also provides the past data that matches refer to.
+pub trait Output {
+    fn push(&mut self, value: u8) -> Result<(), DecodeFailed>;
+
+    fn copy_from_self(&mut self, offset: usize, length: usize) -> Result<(), DecodeFailed>;
+
+    fn copy_from_bitstream(
+        &mut self,
+        bitstream: &mut Bitstream,
+        len: usize,
+    ) -> Result<(), DecodeFailed>;
+}
+
 impl WindowSize {
     /// The window size determines the number of window subdivisions, or position slots.
     pub(crate) fn position_slots(&self) -> usize {
@@ -63,10 +89,21 @@ impl WindowSize {
         }
     }
 
-    fn value(&self) -> usize {
+    pub(crate) fn value(&self) -> usize {
         *self as usize
     }
 
+    /// The window size with the given value, if it is one of the valid sizes.
+    pub(crate) fn from_value(value: usize) -> Option<Self> {
+        use WindowSize::*;
+
+        [
+            KB32, KB64, KB128, KB256, KB512, MB1, MB2, MB4, MB8, MB16, MB32,
+        ]
+        .into_iter()
+        .find(|size| size.value() == value)
+    }
+
     pub(crate) fn create_buffer(&self) -> Window {
         // The window must be at least as big as the smallest chunk, or else we can't possibly
         // contain an entire chunk inside of the sliding window.
@@ -90,6 +127,38 @@ impl Window {
         }
     }
 
+    /// Pre-loads the window with reference data.
+    ///
+    /// As described in [`WindowSize`], the reference takes up its size rounded up to a multiple
+    /// of 32_768 bytes. The rounding only pads in front of the data (with the zeros already in
+    /// the buffer), so the last byte of the reference sits right before the position where the
+    /// subject data begins, and matches reaching back past the start of the output land in it.
+    pub fn load_reference(&mut self, reference: &[u8]) -> Result<(), DecodeFailed> {
+        let rounded_len = reference.len().div_ceil(MAX_CHUNK_SIZE) * MAX_CHUNK_SIZE;
+        if rounded_len > self.buffer.len() {
+            return Err(DecodeFailed::WindowTooSmall);
+        }
+
+        let start = self.buffer.len() - reference.len();
+        self.buffer[start..].copy_from_slice(reference);
+        self.pos = 0;
+        Ok(())
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Restores the position and contents of the window, which must be as long as the buffer.
+    pub fn restore(&mut self, pos: usize, buffer: &[u8]) {
+        self.pos = pos;
+        self.buffer.copy_from_slice(buffer);
+    }
+
     pub fn push(&mut self, value: u8) {
         self.buffer[self.pos] = value;
         self.advance(1);
@@ -173,6 +242,99 @@ impl Window {
     }
 }
 
+impl Output for Window {
+    fn push(&mut self, value: u8) -> Result<(), DecodeFailed> {
+        Window::push(self, value);
+        Ok(())
+    }
+
+    fn copy_from_self(&mut self, offset: usize, length: usize) -> Result<(), DecodeFailed> {
+        Window::copy_from_self(self, offset, length);
+        Ok(())
+    }
+
+    fn copy_from_bitstream(
+        &mut self,
+        bitstream: &mut Bitstream,
+        len: usize,
+    ) -> Result<(), DecodeFailed> {
+        Window::copy_from_bitstream(self, bitstream, len)
+    }
+}
+
+impl FlatWindow {
+    pub fn new(window_size: WindowSize, len: usize) -> Self {
+        Self {
+            pos: 0,
+            window_size: window_size.value(),
+            buffer: vec![0; len],
+        }
+    }
+
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    /// The data decompressed so far, in its entirety.
+    pub fn past_view(&mut self) -> &mut [u8] {
+        &mut self.buffer[..self.pos]
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+
+    /// Makes sure there's room for `len` more bytes.
+    fn reserve(&self, len: usize) -> Result<(), DecodeFailed> {
+        if len > self.buffer.len() - self.pos {
+            Err(DecodeFailed::OverreadChunk)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Output for FlatWindow {
+    fn push(&mut self, value: u8) -> Result<(), DecodeFailed> {
+        self.reserve(1)?;
+        self.buffer[self.pos] = value;
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn copy_from_self(&mut self, offset: usize, length: usize) -> Result<(), DecodeFailed> {
+        if offset == 0 || offset > self.pos || offset > self.window_size {
+            return Err(DecodeFailed::InvalidMatchOffset(offset));
+        }
+        self.reserve(length)?;
+
+        let start = self.pos - offset;
+        if length <= offset {
+            self.buffer.copy_within(start..start + length, self.pos);
+        } else {
+            // The source overlaps with the destination, which repeats the data, so it must be
+            // copied as we go.
+            for i in 0..length {
+                self.buffer[self.pos + i] = self.buffer[start + i];
+            }
+        }
+
+        self.pos += length;
+        Ok(())
+    }
+
+    fn copy_from_bitstream(
+        &mut self,
+        bitstream: &mut Bitstream,
+        len: usize,
+    ) -> Result<(), DecodeFailed> {
+        self.reserve(len)?;
+        bitstream.read_raw(&mut self.buffer[self.pos..self.pos + len])?;
+        self.pos += len;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -343,7 +505,7 @@ mod tests {
     fn check_past_view_too_long() {
         let mut window = WindowSize::KB32.create_buffer();
         assert_eq!(
-            window.past_view(1 << 15 + 1),
+            window.past_view(1 << (15 + 1)),
             Err(DecodeFailed::ChunkTooLong)
         );
     }
@@ -360,4 +522,53 @@ mod tests {
         window.pos = 123;
         assert!(window.past_view(1 << 15).is_ok());
     }
+
+    #[test]
+    fn check_load_reference() {
+        let mut window = WindowSize::KB64.create_buffer();
+        window.load_reference(&[1, 2, 3]).unwrap();
+        assert_eq!(window.pos, 0);
+        assert_eq!(&window.buffer[window.buffer.len() - 3..], &[1, 2, 3]);
+        window.copy_from_self(3, 2);
+        assert_eq!(&window.buffer[..2], &[1, 2]);
+    }
+
+    #[test]
+    fn check_load_reference_too_long() {
+        let mut window = WindowSize::KB32.create_buffer();
+        assert_eq!(
+            window.load_reference(&[0; (1 << 15) + 1]),
+            Err(DecodeFailed::WindowTooSmall)
+        );
+    }
+
+    #[test]
+    fn check_flat_copy_from_self_overlap() {
+        let mut window = FlatWindow::new(WindowSize::KB32, 6);
+        window.push(1).unwrap();
+        window.push(2).unwrap();
+        window.push(3).unwrap();
+        window.copy_from_self(2, 3).unwrap();
+        assert_eq!(window.past_view(), &[1, 2, 3, 2, 3, 2]);
+    }
+
+    #[test]
+    fn check_flat_copy_from_self_before_start() {
+        let mut window = FlatWindow::new(WindowSize::KB32, 6);
+        window.push(1).unwrap();
+        assert_eq!(
+            window.copy_from_self(2, 2),
+            Err(DecodeFailed::InvalidMatchOffset(2))
+        );
+    }
+
+    #[test]
+    fn check_flat_copy_from_self_too_long() {
+        let mut window = FlatWindow::new(WindowSize::KB32, 3);
+        window.push(1).unwrap();
+        assert_eq!(
+            window.copy_from_self(1, 3),
+            Err(DecodeFailed::OverreadChunk)
+        );
+    }
 }
@@ -0,0 +1,232 @@
+use std::io::{self, Write};
+
+use crate::{Framing, LzxdEncoder, MAX_CHUNK_SIZE};
+
+/// A [`Write`] adapter that compresses the data written to it into a framed LZXD stream.
+///
+/// Data is buffered until there's a whole chunk of [`MAX_CHUNK_SIZE`] bytes, which is then
+/// compressed and written to the inner writer delimited according to the given [`Framing`].
+/// Without one, the compressed chunks are written back to back, which is only useful when the
+/// container keeps track of their sizes by other means.
+///
+/// The last chunk is usually shorter, and since the stream ends there, it can only be written
+/// by calling [`Self::finish`] once all the data has been written.
+///
+/// If writing a compressed chunk to the inner writer fails, the encoder has already moved past
+/// it, so the stream can't be continued and every later write fails too.
+///
+/// ```
+/// # fn main() -> std::io::Result<()> {
+/// use std::io::{Read, Write};
+/// use ::lzxd::{Framing, Lzxd, LzxdEncoder, LzxdReader, LzxdWriter, WindowSize};
+///
+/// let data = b"Hello, world! ".repeat(10000);
+///
+/// let encoder = LzxdEncoder::new(WindowSize::KB64);
+/// let mut writer = LzxdWriter::new(Vec::new(), encoder, Some(Framing::Xnb));
+/// writer.write_all(&data)?;
+/// let compressed = writer.finish()?;
+///
+/// let lzxd = Lzxd::new(WindowSize::KB64);
+/// let mut reader = LzxdReader::new(&compressed[..], lzxd, Framing::Xnb, data.len() as u64);
+/// let mut decompressed = Vec::new();
+/// reader.read_to_end(&mut decompressed)?;
+/// assert_eq!(decompressed, data);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Write`]: https://doc.rust-lang.org/std/io/trait.Write.html
+/// [`MAX_CHUNK_SIZE`]: constant.MAX_CHUNK_SIZE.html
+/// [`Framing`]: enum.Framing.html
+pub struct LzxdWriter<W: Write> {
+    /// The writer receiving the compressed data stream.
+    inner: W,
+
+    /// The encoder used to compress each chunk.
+    encoder: LzxdEncoder,
+
+    /// How the compressed chunks are delimited, if at all.
+    framing: Option<Framing>,
+
+    /// Buffer holding the data of the next chunk.
+    chunk: Vec<u8>,
+
+    /// Buffer holding the header and data of the last compressed chunk.
+    output: Vec<u8>,
+
+    /// Whether writing a compressed chunk to the inner writer failed.
+    poisoned: bool,
+}
+
+impl<W: Write> LzxdWriter<W> {
+    /// Creates a new writer compressing the data written to it into `inner` with the given
+    /// encoder.
+    pub fn new(inner: W, encoder: LzxdEncoder, framing: Option<Framing>) -> Self {
+        Self {
+            inner,
+            encoder,
+            framing,
+            chunk: Vec::with_capacity(MAX_CHUNK_SIZE),
+            output: Vec::new(),
+            poisoned: false,
+        }
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Fails if a previous chunk could not be written out, since the stream can't continue.
+    fn check_poisoned(&self) -> io::Result<()> {
+        if self.poisoned {
+            Err(io::Error::other("a previous chunk failed to be written"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Compresses the buffered chunk and writes it out.
+    fn write_chunk(&mut self) -> io::Result<()> {
+        self.check_poisoned()?;
+        let compressed = self
+            .encoder
+            .compress_next(&self.chunk)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        self.output.clear();
+        if let Some(framing) = self.framing {
+            framing.write_header(compressed.len(), self.chunk.len(), &mut self.output);
+        }
+        self.output.extend_from_slice(compressed);
+        // The encoder has already moved past this chunk, so it can't be compressed again.
+        self.poisoned = true;
+        self.inner.write_all(&self.output)?;
+        self.poisoned = false;
+
+        self.chunk.clear();
+        Ok(())
+    }
+
+    /// Compresses and writes out the last chunk, ending the stream, and returns the
+    /// underlying writer.
+    ///
+    /// Nothing is written if all the chunks were full, since the stream ends after any of
+    /// them.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.chunk.is_empty() {
+            self.write_chunk()?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for LzxdWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_poisoned()?;
+        let len = buf.len().min(MAX_CHUNK_SIZE - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..len]);
+        if self.chunk.len() == MAX_CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(len)
+    }
+
+    /// Flushes the underlying writer. The buffered data of a chunk that isn't full yet is not
+    /// written, because a shorter chunk would end the stream.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Lzxd, LzxdReader, WindowSize};
+    use std::io::Read;
+
+    fn data() -> Vec<u8> {
+        (0..3 * MAX_CHUNK_SIZE + 1234)
+            .map(|i| (i % 251) as u8 ^ (i / 1000) as u8)
+            .collect()
+    }
+
+    /// Writes the data in pieces of awkward sizes.
+    fn compress(data: &[u8], framing: Option<Framing>) -> Vec<u8> {
+        let encoder = LzxdEncoder::new(WindowSize::KB64);
+        let mut writer = LzxdWriter::new(Vec::new(), encoder, framing);
+        for piece in data.chunks(10_000) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn decompress(compressed: &[u8], framing: Framing, len: usize) -> Vec<u8> {
+        let lzxd = Lzxd::new(WindowSize::KB64);
+        let mut reader = LzxdReader::new(compressed, lzxd, framing, len as u64);
+        let mut output = Vec::new();
+        reader.read_to_end(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn write_ms_patch() {
+        let data = data();
+        let compressed = compress(&data, Some(Framing::MsPatch));
+        assert_eq!(decompress(&compressed, Framing::MsPatch, data.len()), data);
+    }
+
+    #[test]
+    fn write_xnb() {
+        let data = data();
+        let compressed = compress(&data, Some(Framing::Xnb));
+        assert_eq!(decompress(&compressed, Framing::Xnb, data.len()), data);
+    }
+
+    #[test]
+    fn write_raw() {
+        let data = data();
+        let mut encoder = LzxdEncoder::new(WindowSize::KB64);
+        let expected = data
+            .chunks(MAX_CHUNK_SIZE)
+            .flat_map(|chunk| encoder.compress_next(chunk).unwrap().to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(compress(&data, None), expected);
+    }
+
+    #[test]
+    fn write_after_error() {
+        /// A writer whose first write fails.
+        struct FailOnce(bool);
+        impl Write for FailOnce {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if std::mem::replace(&mut self.0, false) {
+                    Err(io::Error::other("failed"))
+                } else {
+                    Ok(buf.len())
+                }
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let data = data();
+        let encoder = LzxdEncoder::new(WindowSize::KB64);
+        let mut writer = LzxdWriter::new(FailOnce(true), encoder, Some(Framing::MsPatch));
+        assert!(writer.write_all(&data[..MAX_CHUNK_SIZE]).is_err());
+        assert!(writer.write_all(&data[MAX_CHUNK_SIZE..]).is_err());
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn write_whole_chunks() {
+        let data = vec![7; 2 * MAX_CHUNK_SIZE];
+        let compressed = compress(&data, Some(Framing::Xnb));
+        assert_eq!(decompress(&compressed, Framing::Xnb, data.len()), data);
+        assert!(compress(&[], Some(Framing::Xnb)).is_empty());
+    }
+}
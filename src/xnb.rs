@@ -0,0 +1,328 @@
+//! Reading of the XNB files produced by XNA Game Studio.
+//!
+//! Every XNB file starts with a short header, which says whether the rest of the file is
+//! compressed. Compressed files use LZX with a 64 KB window, with their chunks delimited as
+//! described in [`Framing::Xnb`], and their header also includes the size of the data once
+//! decompressed.
+//!
+//! ```no_run
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use ::lzxd::xnb;
+//!
+//! let data = std::fs::read("texture.xnb")?;
+//! let (header, payload) = xnb::decompress(&data)?;
+//! println!("XNB version {} for {:?}", header.version(), header.platform());
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Framing::Xnb`]: ../enum.Framing.html#variant.Xnb
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::bytes::ByteReader;
+use crate::{decompress_all, DecompressError, Framing, Variant, WindowSize, MAX_CHUNK_SIZE};
+
+/// Magic bytes at the start of every XNB file.
+const MAGIC: &[u8; 3] = b"XNB";
+
+/// The content is meant for the HiDef graphics profile rather than the Reach one.
+const FLAG_HIDEF: u8 = 0x01;
+
+/// The content is compressed with LZ4, which is used by MonoGame and is not supported.
+const FLAG_COMPRESSED_LZ4: u8 = 0x40;
+
+/// The content is compressed with LZX.
+const FLAG_COMPRESSED_LZX: u8 = 0x80;
+
+/// The window size used to compress the content.
+const WINDOW_SIZE: WindowSize = WindowSize::KB64;
+
+/// The error type used when an XNB file cannot be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XnbError {
+    /// The data does not start with the magic bytes of an XNB file.
+    InvalidMagic,
+
+    /// The data ended before the file was fully read.
+    UnexpectedEof,
+
+    /// The content is compressed with something other than LZX.
+    UnsupportedCompression,
+
+    /// The compressed content could not be decompressed.
+    Decompress(DecompressError),
+}
+
+impl fmt::Display for XnbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use XnbError::*;
+
+        match self {
+            InvalidMagic => write!(f, "data is not an XNB file"),
+            UnexpectedEof => write!(f, "reached end of data without fully reading the XNB file"),
+            UnsupportedCompression => write!(f, "XNB content is not compressed with LZX"),
+            Decompress(e) => write!(f, "failed to decompress XNB content: {}", e),
+        }
+    }
+}
+
+impl core::error::Error for XnbError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Decompress(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecompressError> for XnbError {
+    fn from(e: DecompressError) -> Self {
+        Self::Decompress(e)
+    }
+}
+
+/// The platform the content of an XNB file was built for.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Platform {
+    /// Microsoft Windows, identified by `w`.
+    Windows,
+
+    /// Windows Phone 7, identified by `m`.
+    WindowsPhone,
+
+    /// Xbox 360, identified by `x`.
+    Xbox360,
+
+    /// Some other platform, such as those added by MonoGame.
+    Other(u8),
+}
+
+impl Platform {
+    fn from_id(id: u8) -> Self {
+        match id {
+            b'w' => Self::Windows,
+            b'm' => Self::WindowsPhone,
+            b'x' => Self::Xbox360,
+            _ => Self::Other(id),
+        }
+    }
+}
+
+/// The header at the start of every XNB file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct XnbHeader {
+    platform: Platform,
+    version: u8,
+    flags: u8,
+    file_size: u32,
+    decompressed_size: Option<u32>,
+}
+
+impl XnbHeader {
+    /// Reads the header at the start of the XNB file.
+    pub fn new(data: &[u8]) -> Result<Self, XnbError> {
+        let mut reader = ByteReader::new(data, XnbError::UnexpectedEof);
+        if reader.read(3)? != MAGIC {
+            return Err(XnbError::InvalidMagic);
+        }
+
+        let platform = Platform::from_id(reader.read_u8()?);
+        let version = reader.read_u8()?;
+        let flags = reader.read_u8()?;
+        let file_size = reader.read_u32()?;
+        let decompressed_size = if flags & (FLAG_COMPRESSED_LZX | FLAG_COMPRESSED_LZ4) != 0 {
+            Some(reader.read_u32()?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            platform,
+            version,
+            flags,
+            file_size,
+            decompressed_size,
+        })
+    }
+
+    /// The platform the content was built for.
+    pub fn platform(&self) -> Platform {
+        self.platform
+    }
+
+    /// The version of the format, which is 5 for XNA Game Studio 4.0, 4 for 3.1 and 3 for
+    /// 3.0.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Whether the content is meant for the HiDef graphics profile, rather than Reach.
+    pub fn hidef(&self) -> bool {
+        self.flags & FLAG_HIDEF != 0
+    }
+
+    /// Whether the content is compressed with LZX.
+    pub fn compressed(&self) -> bool {
+        self.flags & FLAG_COMPRESSED_LZX != 0
+    }
+
+    /// The size of the whole file, including the header.
+    pub fn file_size(&self) -> u32 {
+        self.file_size
+    }
+
+    /// The size of the content once decompressed, if it's compressed.
+    pub fn decompressed_size(&self) -> Option<u32> {
+        self.decompressed_size
+    }
+
+    /// The length of the header itself.
+    fn len(&self) -> usize {
+        if self.decompressed_size.is_some() {
+            14
+        } else {
+            10
+        }
+    }
+}
+
+/// Reads the header of the XNB file and returns it along with its content, decompressing it
+/// if needed.
+pub fn decompress(data: &[u8]) -> Result<(XnbHeader, Vec<u8>), XnbError> {
+    let header = XnbHeader::new(data)?;
+    if header.flags & FLAG_COMPRESSED_LZ4 != 0 {
+        return Err(XnbError::UnsupportedCompression);
+    }
+
+    let content = data
+        .get(header.len()..header.file_size as usize)
+        .ok_or(XnbError::UnexpectedEof)?;
+    let payload = match header.decompressed_size {
+        // Every chunk takes at least a byte, so a larger size could never be reached, and
+        // the whole output is allocated up front.
+        Some(size) if size as usize > content.len().saturating_mul(MAX_CHUNK_SIZE) => {
+            return Err(XnbError::UnexpectedEof);
+        }
+        Some(size) => decompress_all(
+            content,
+            Framing::Xnb,
+            WINDOW_SIZE,
+            Variant::Lzx,
+            size as usize,
+        )?,
+        None => content.to_vec(),
+    };
+    Ok((header, payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LzxdEncoder;
+
+    /// Builds an XNB file for Windows with the given flags and content, compressing it if
+    /// the flags say so.
+    fn build(flags: u8, content: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        if flags & FLAG_COMPRESSED_LZX != 0 {
+            let compressed = LzxdEncoder::new(WINDOW_SIZE).compress_chunks(content);
+            for (chunk, data) in compressed.iter().zip(content.chunks(MAX_CHUNK_SIZE)) {
+                if data.len() != MAX_CHUNK_SIZE {
+                    body.push(0xFF);
+                    body.extend((data.len() as u16).to_be_bytes());
+                }
+                body.extend((chunk.len() as u16).to_be_bytes());
+                body.extend(chunk);
+            }
+        } else {
+            body.extend(content);
+        }
+
+        let mut out = Vec::new();
+        out.extend(MAGIC);
+        out.extend([b'w', 5, flags]);
+        let header_len = if flags & FLAG_COMPRESSED_LZX != 0 {
+            14
+        } else {
+            10
+        };
+        out.extend(((header_len + body.len()) as u32).to_le_bytes());
+        if flags & FLAG_COMPRESSED_LZX != 0 {
+            out.extend((content.len() as u32).to_le_bytes());
+        }
+        out.extend(body);
+        out
+    }
+
+    fn content() -> Vec<u8> {
+        (0..100_000)
+            .map(|i| (i % 251) as u8 ^ (i / 700) as u8)
+            .collect()
+    }
+
+    #[test]
+    fn read_compressed() {
+        let content = content();
+        let data = build(FLAG_COMPRESSED_LZX | FLAG_HIDEF, &content);
+        assert!(data.len() < content.len());
+
+        let (header, payload) = decompress(&data).unwrap();
+        assert_eq!(header.platform(), Platform::Windows);
+        assert_eq!(header.version(), 5);
+        assert!(header.hidef());
+        assert!(header.compressed());
+        assert_eq!(header.file_size() as usize, data.len());
+        assert_eq!(header.decompressed_size(), Some(content.len() as u32));
+        assert_eq!(payload, content);
+    }
+
+    #[test]
+    fn read_uncompressed() {
+        let content = content();
+        let mut data = build(0, &content);
+        // Anything after the size given by the header is not part of the file.
+        data.extend(b"trailing");
+
+        let (header, payload) = decompress(&data).unwrap();
+        assert!(!header.hidef());
+        assert!(!header.compressed());
+        assert_eq!(header.decompressed_size(), None);
+        assert_eq!(payload, content);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(decompress(b"XNA").err(), Some(XnbError::InvalidMagic));
+        assert_eq!(decompress(b"XNBw").err(), Some(XnbError::UnexpectedEof));
+
+        let data = build(0, b"content");
+        assert_eq!(
+            decompress(&data[..data.len() - 1]).err(),
+            Some(XnbError::UnexpectedEof)
+        );
+
+        // A decompressed size far larger than the content could hold.
+        let data = b"XNBw\x05\x80\x0E\x00\x00\x00\xFF\xFF\xFF\xFF";
+        assert_eq!(decompress(data).err(), Some(XnbError::UnexpectedEof));
+
+        let mut data = build(FLAG_COMPRESSED_LZX, b"content");
+        data[5] = FLAG_COMPRESSED_LZ4;
+        assert_eq!(
+            decompress(&data).err(),
+            Some(XnbError::UnsupportedCompression)
+        );
+    }
+
+    #[test]
+    fn read_header() {
+        let header = XnbHeader::new(b"XNBx\x04\x00\x0A\x00\x00\x00").unwrap();
+        assert_eq!(header.platform(), Platform::Xbox360);
+        assert_eq!(header.version(), 4);
+        assert_eq!(header.file_size(), 10);
+
+        let header = XnbHeader::new(b"XNBa\x05\x80\x0E\x00\x00\x00\x00\x01\x00\x00").unwrap();
+        assert_eq!(header.platform(), Platform::Other(b'a'));
+        assert_eq!(header.decompressed_size(), Some(0x100));
+    }
+}